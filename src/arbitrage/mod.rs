@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::Arc};
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    config::{Config, DEFAULT_MAX_NOTIONAL},
+    error::ExecutorError,
+    markets::{
+        deepest_spot_rate, f64_to_u256, fee_fraction, pool_decimals, spot_rate, u256_to_f64,
+    },
+    routing::{graph::TokenGraph, simulate_path_amounts, QuoteContext},
+};
+
+pub mod negative_cycle;
+pub mod sizing;
+
+use sizing::optimal_trade_size;
+
+// A closed path start -> ... -> start, `tokens` begins and ends with the start token
+#[derive(Debug, Clone)]
+pub struct Cycle {
+    pub tokens: Vec<H160>,
+    pub pools: Vec<Pool>,
+}
+
+impl Cycle {
+    pub fn start_token(&self) -> H160 {
+        self.tokens[0]
+    }
+
+    // Product of the spot rates after fees, above 1.0 means the cycle is profitable at the margin
+    pub fn spot_return(&self) -> f64 {
+        self.pools
+            .iter()
+            .zip(&self.tokens)
+            .map(|(pool, token_in)| {
+                spot_rate(pool, *token_in).unwrap_or(0.0) * (1.0 - fee_fraction(pool))
+            })
+            .product()
+    }
+
+    // Runs `amount_in` through every pool of the cycle and returns the amount back in the start token
+    pub async fn simulate<M: 'static + Middleware>(
+        &self,
+        amount_in: U256,
+        context: &QuoteContext<'_>,
+        middleware: Arc<M>,
+    ) -> Result<U256, ExecutorError<M>> {
+        let amounts = simulate_path_amounts(
+            &self.pools,
+            self.start_token(),
+            amount_in,
+            context,
+            middleware,
+        )
+        .await?;
+        Ok(*amounts.last().unwrap())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArbitrageOpportunity {
+    pub cycle: Cycle,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub profit: U256,
+    // Profit in wei of the wrapped native token at spot, None when the start token has no market
+    // with it
+    pub native_profit: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArbitrageOptions {
+    // Tokens cycles start and end in, profit is measured in these
+    pub start_tokens: Vec<H160>,
+    // Profits in different start tokens are compared in this token
+    pub wrapped_native: H160,
+    // Largest input tried for a cycle, in whole units of the start token
+    pub max_notional: f64,
+    // 2 or 3 pools per cycle
+    pub max_cycle_length: usize,
+}
+
+impl ArbitrageOptions {
+    pub fn from_config(configuration: &Config) -> ArbitrageOptions {
+        ArbitrageOptions {
+            start_tokens: configuration.intermediate_tokens.clone(),
+            wrapped_native: configuration.weth_address,
+            max_notional: configuration.max_notional,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ArbitrageOptions {
+    fn default() -> ArbitrageOptions {
+        ArbitrageOptions {
+            start_tokens: vec![],
+            wrapped_native: H160::zero(),
+            max_notional: DEFAULT_MAX_NOTIONAL,
+            max_cycle_length: 3,
+        }
+    }
+}
+
+// Enumerates every 2 and 3 pool cycle through each start token, each pool used at most once
+pub fn find_cycles(
+    graph: &TokenGraph,
+    start_tokens: &[H160],
+    max_cycle_length: usize,
+) -> Vec<Cycle> {
+    let mut cycles = vec![];
+
+    for &start in start_tokens {
+        for first in graph.edges(start) {
+            let token_b = first.token_out;
+
+            for second in graph.edges(token_b) {
+                if second.pool.address() == first.pool.address() {
+                    continue;
+                }
+
+                // start -> b -> start
+                if second.token_out == start {
+                    cycles.push(Cycle {
+                        tokens: vec![start, token_b, start],
+                        pools: vec![first.pool, second.pool],
+                    });
+                    continue;
+                }
+
+                if max_cycle_length < 3 {
+                    continue;
+                }
+
+                // start -> b -> c -> start
+                let token_c = second.token_out;
+                for third in graph.edges(token_c) {
+                    if third.token_out != start
+                        || third.pool.address() == first.pool.address()
+                        || third.pool.address() == second.pool.address()
+                    {
+                        continue;
+                    }
+
+                    cycles.push(Cycle {
+                        tokens: vec![start, token_b, token_c, start],
+                        pools: vec![first.pool, second.pool, third.pool],
+                    });
+                }
+            }
+        }
+    }
+
+    cycles
+}
+
+// Simulates every cycle whose spot return beats its fees at each probe size,
+// and returns the profitable ones with their best probe, most profitable first
+pub async fn find_arbitrage_opportunities<M: 'static + Middleware>(
+    graph: &TokenGraph,
+    options: &ArbitrageOptions,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Vec<ArbitrageOpportunity>, ExecutorError<M>> {
+    let cycles = find_cycles(graph, &options.start_tokens, options.max_cycle_length)
+        .into_iter()
+        .filter(|cycle| cycle.spot_return() > 1.0)
+        .collect();
+
+    simulate_cycles(graph, cycles, options, context, middleware).await
+}
+
+// Raw units of the wrapped native token per raw unit of `token`, from the deepest pool between
+// the two in the graph
+pub fn native_rate(graph: &TokenGraph, token: H160, wrapped_native: H160) -> Option<f64> {
+    if token == wrapped_native {
+        return Some(1.0);
+    }
+
+    deepest_spot_rate(
+        graph
+            .edges(token)
+            .iter()
+            .filter(|edge| edge.token_out == wrapped_native)
+            .map(|edge| &edge.pool),
+        token,
+    )
+}
+
+// Sizes every cycle with the trade size solver and keeps the profitable ones. Profits are in
+// different start tokens, so they're ranked on their wrapped native value, most profitable first,
+// with the start tokens that can't be priced last.
+pub async fn simulate_cycles<M: 'static + Middleware>(
+    graph: &TokenGraph,
+    cycles: Vec<Cycle>,
+    options: &ArbitrageOptions,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Vec<ArbitrageOpportunity>, ExecutorError<M>> {
+    let mut opportunities = vec![];
+    let mut native_rates: HashMap<H160, Option<f64>> = HashMap::new();
+
+    for cycle in cycles {
+        let (decimals, _) = pool_decimals(&cycle.pools[0], cycle.start_token());
+        let max_amount_in = f64_to_u256(options.max_notional * 10_f64.powi(decimals as i32));
+
+        if let Some(size) = optimal_trade_size(
+            &cycle.pools,
+            cycle.start_token(),
+            max_amount_in,
+            context,
+            middleware.clone(),
+        )
+        .await?
+        {
+            let native_rate = *native_rates
+                .entry(cycle.start_token())
+                .or_insert_with(|| native_rate(graph, cycle.start_token(), options.wrapped_native));
+
+            opportunities.push(ArbitrageOpportunity {
+                cycle,
+                amount_in: size.amount_in,
+                amount_out: size.amount_out,
+                profit: size.profit,
+                native_profit: native_rate.map(|rate| u256_to_f64(size.profit) * rate),
+            });
+        }
+    }
+
+    opportunities.sort_by(|a, b| match (a.native_profit, b.native_profit) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+
+    Ok(opportunities)
+}
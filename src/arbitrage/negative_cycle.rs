@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use cfmms::pool::Pool;
+use ethers::{providers::Middleware, types::H160};
+
+use crate::{
+    arbitrage::{simulate_cycles, ArbitrageOpportunity, ArbitrageOptions, Cycle},
+    error::ExecutorError,
+    markets::{fee_fraction, spot_rate},
+    routing::{graph::TokenGraph, QuoteContext},
+};
+
+const NONE: usize = usize::MAX;
+
+// Cycles whose log weight is this close to zero are rounding noise
+const MIN_CYCLE_WEIGHT: f64 = -1e-9;
+
+// Cycles up to this many swaps are caught on the edge that closes them, longer ones after the
+// round they close in
+const MAX_CLOSING_CHECK_LENGTH: usize = 6;
+
+struct WeightedEdge {
+    from: usize,
+    to: usize,
+    pool: Pool,
+    weight: f64,
+    enabled: bool,
+}
+
+// Token graph flattened into indexed nodes, edges weighted by -ln(spot rate after fee),
+// so a cycle with a negative total weight returns more than it takes in
+pub struct LogPriceGraph {
+    tokens: Vec<H160>,
+    edges: Vec<WeightedEdge>,
+    // Outgoing edge indices per node
+    adjacency: Vec<Vec<usize>>,
+}
+
+impl LogPriceGraph {
+    pub fn new(graph: &TokenGraph) -> LogPriceGraph {
+        let tokens: Vec<H160> = graph.tokens().collect();
+        let index: HashMap<H160, usize> = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (*token, i))
+            .collect();
+
+        let mut edges = vec![];
+        let mut adjacency = vec![vec![]; tokens.len()];
+
+        for (from, token) in tokens.iter().enumerate() {
+            for edge in graph.edges(*token) {
+                let rate = match spot_rate(&edge.pool, *token) {
+                    Some(rate) => rate * (1.0 - fee_fraction(&edge.pool)),
+                    None => continue,
+                };
+                if rate <= 0.0 {
+                    continue;
+                }
+
+                adjacency[from].push(edges.len());
+                edges.push(WeightedEdge {
+                    from,
+                    to: index[&edge.token_out],
+                    pool: edge.pool,
+                    weight: -rate.ln(),
+                    enabled: true,
+                });
+            }
+        }
+
+        LogPriceGraph {
+            tokens,
+            edges,
+            adjacency,
+        }
+    }
+
+    // Bellman-Ford from the base tokens, only relaxing edges out of nodes that improved in the
+    // previous round. An edge whose target is a few hops up the path to its source would close a
+    // cycle, the cycle is recorded and its edges disabled instead of relaxing it. Longer cycles are
+    // found in the predecessor graph after each round. Catching cycles as they close keeps their
+    // losses from spreading through the whole graph, so the search settles in a few rounds.
+    // With no base tokens every token is a source.
+    pub fn find_negative_cycles(&mut self, base_tokens: &[H160]) -> Vec<Cycle> {
+        let node_count = self.tokens.len();
+        let mut distance = vec![f64::INFINITY; node_count];
+        let mut predecessor = vec![NONE; node_count];
+        let mut queued = vec![false; node_count];
+        // Walk that last passed each node, see predecessor_cycles
+        let mut walk = vec![0; node_count];
+        let mut walk_count = 0;
+
+        let mut active: Vec<usize> = match base_tokens.is_empty() {
+            true => (0..node_count).collect(),
+            false => self
+                .tokens
+                .iter()
+                .enumerate()
+                .filter(|(_, token)| base_tokens.contains(token))
+                .map(|(i, _)| i)
+                .collect(),
+        };
+        for source in &active {
+            distance[*source] = 0.0;
+        }
+
+        let mut cycles = vec![];
+        let mut seen: HashSet<Vec<H160>> = HashSet::new();
+
+        for _ in 0..node_count {
+            let mut next_active = vec![];
+
+            for &from in &active {
+                for k in 0..self.adjacency[from].len() {
+                    let edge_index = self.adjacency[from][k];
+                    let edge = &self.edges[edge_index];
+                    if !edge.enabled || distance[from] + edge.weight >= distance[edge.to] - 1e-12 {
+                        continue;
+                    }
+
+                    let to = edge.to;
+                    if let Some(mut cycle_edges) =
+                        self.tree_path(&predecessor, to, from, MAX_CLOSING_CHECK_LENGTH - 1)
+                    {
+                        cycle_edges.push(edge_index);
+                        self.record_cycle(cycle_edges, base_tokens, &mut seen, &mut cycles);
+                        continue;
+                    }
+
+                    distance[to] = distance[from] + edge.weight;
+                    predecessor[to] = edge_index;
+                    if !queued[to] {
+                        queued[to] = true;
+                        next_active.push(to);
+                    }
+                }
+            }
+            for node in &next_active {
+                queued[*node] = false;
+            }
+
+            let round_cycles =
+                self.predecessor_cycles(&predecessor, &next_active, &mut walk, &mut walk_count);
+            for cycle_edges in round_cycles {
+                for &edge_index in &cycle_edges {
+                    predecessor[self.edges[edge_index].to] = NONE;
+                }
+                self.record_cycle(cycle_edges, base_tokens, &mut seen, &mut cycles);
+            }
+
+            if next_active.is_empty() {
+                break;
+            }
+            active = next_active;
+        }
+
+        cycles
+    }
+
+    // Disables the cycle's edges so the search settles, and keeps it when it's profitable, passes
+    // through a base token and wasn't found before
+    fn record_cycle(
+        &mut self,
+        cycle_edges: Vec<usize>,
+        base_tokens: &[H160],
+        seen: &mut HashSet<Vec<H160>>,
+        cycles: &mut Vec<Cycle>,
+    ) {
+        let enabled = cycle_edges.iter().all(|i| self.edges[*i].enabled);
+        for &edge_index in &cycle_edges {
+            self.edges[edge_index].enabled = false;
+        }
+
+        let weight: f64 = cycle_edges.iter().map(|i| self.edges[*i].weight).sum();
+        if !enabled || weight >= MIN_CYCLE_WEIGHT {
+            return;
+        }
+
+        // Cycles downstream of a base token that never pass through one can't be traded from it
+        let cycle = match self.to_cycle(&cycle_edges, base_tokens) {
+            Some(cycle) => cycle,
+            None => return,
+        };
+        let mut key: Vec<H160> = cycle.pools.iter().map(|pool| pool.address()).collect();
+        key.sort();
+        if seen.insert(key) {
+            cycles.push(cycle);
+        }
+    }
+
+    // Edge indices in swap order of the predecessor path from `ancestor` down to `node`, None when
+    // `ancestor` isn't within `max_length` hops up from `node`
+    fn tree_path(
+        &self,
+        predecessor: &[usize],
+        ancestor: usize,
+        node: usize,
+        max_length: usize,
+    ) -> Option<Vec<usize>> {
+        let mut path = vec![];
+        let mut current = node;
+        while current != ancestor {
+            let edge_index = predecessor[current];
+            if edge_index == NONE || path.len() == max_length {
+                return None;
+            }
+            path.push(edge_index);
+            current = self.edges[edge_index].from;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    // Cycles in the predecessor graph as edge indices in swap order. A cycle that formed this round
+    // passes through a node whose predecessor just changed, so the walks only start from the nodes
+    // relaxed this round. `walk` keeps the number of the walk that last passed each node and
+    // `walk_count` the number of walks so far, so neither is reset between rounds.
+    fn predecessor_cycles(
+        &self,
+        predecessor: &[usize],
+        relaxed: &[usize],
+        walk: &mut [usize],
+        walk_count: &mut usize,
+    ) -> Vec<Vec<usize>> {
+        let first_walk = *walk_count + 1;
+        let mut cycles = vec![];
+
+        for &start in relaxed {
+            *walk_count += 1;
+            let current_walk = *walk_count;
+
+            let mut node = start;
+            while node != NONE && walk[node] < first_walk {
+                walk[node] = current_walk;
+                node = match predecessor[node] {
+                    NONE => NONE,
+                    edge_index => self.edges[edge_index].from,
+                };
+            }
+
+            // Walked back into a node of this same walk, so it sits on a cycle
+            if node == NONE || walk[node] != current_walk {
+                continue;
+            }
+
+            let mut cycle_edges = vec![];
+            let mut current = node;
+            loop {
+                let edge_index = predecessor[current];
+                cycle_edges.push(edge_index);
+                current = self.edges[edge_index].from;
+                if current == node {
+                    break;
+                }
+            }
+            cycle_edges.reverse();
+            cycles.push(cycle_edges);
+        }
+
+        cycles
+    }
+
+    // Rotates the cycle to start at a base token, None when it doesn't pass through one. With no
+    // base tokens every cycle is kept as found.
+    fn to_cycle(&self, cycle_edges: &[usize], base_tokens: &[H160]) -> Option<Cycle> {
+        let offset = match base_tokens.is_empty() {
+            true => 0,
+            false => cycle_edges
+                .iter()
+                .position(|i| base_tokens.contains(&self.tokens[self.edges[*i].from]))?,
+        };
+
+        let mut tokens = vec![];
+        let mut pools = vec![];
+        for k in 0..cycle_edges.len() {
+            let edge = &self.edges[cycle_edges[(offset + k) % cycle_edges.len()]];
+            tokens.push(self.tokens[edge.from]);
+            pools.push(edge.pool);
+        }
+        tokens.push(tokens[0]);
+
+        Some(Cycle { tokens, pools })
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+}
+
+// Finds negative cycles reachable from the start tokens and keeps those that stay profitable
+// under exact simulation
+pub async fn find_negative_cycle_opportunities<M: 'static + Middleware>(
+    graph: &TokenGraph,
+    options: &ArbitrageOptions,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Vec<ArbitrageOpportunity>, ExecutorError<M>> {
+    let cycles = LogPriceGraph::new(graph).find_negative_cycles(&options.start_tokens);
+
+    simulate_cycles(graph, cycles, options, context, middleware).await
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    error::ExecutorError,
+    markets::{f64_to_u256, fee_fraction, pool_token_out, u256_to_f64},
+    routing::{simulate_path_amounts, QuoteContext},
+};
+
+// Width of the remaining bracket, relative to its upper end, at which the golden section search
+// stops. Each step narrows the bracket by ~38%, so this takes about 30 simulations.
+const GOLDEN_SECTION_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TradeSize {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub profit: U256,
+}
+
+// Input that maximises the profit of swapping `token_in` around the cycle `pools`, capped at
+// `max_amount_in`. None when no input size is profitable. Pools are simulated as by the routers,
+// V3 pools from the tick data of `context`.
+pub async fn optimal_trade_size<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    max_amount_in: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Option<TradeSize>, ExecutorError<M>> {
+    if pools.is_empty() || max_amount_in.is_zero() {
+        return Ok(None);
+    }
+
+    let amount_in = match v2_optimal_amount_in(pools, token_in) {
+        Some(amount_in) => amount_in.min(u256_to_f64(max_amount_in)),
+        None if pools.iter().all(|pool| matches!(pool, Pool::UniswapV2(_))) => return Ok(None),
+        None => {
+            golden_section_search(pools, token_in, max_amount_in, context, middleware.clone()).await
+        }
+    };
+
+    let amount_in = f64_to_u256(amount_in).min(max_amount_in);
+    if amount_in.is_zero() {
+        return Ok(None);
+    }
+
+    let amount_out =
+        match simulate_path_amounts(pools, token_in, amount_in, context, middleware).await {
+            Ok(amounts) => *amounts.last().unwrap(),
+            Err(_) => return Ok(None),
+        };
+
+    match amount_out > amount_in {
+        true => Ok(Some(TradeSize {
+            amount_in,
+            amount_out,
+            profit: amount_out - amount_in,
+        })),
+        false => Ok(None),
+    }
+}
+
+// A chain of constant product pools behaves like a single one, out = g * x * b / (a + g * x),
+// whose profit out - x peaks at x = (sqrt(g * a * b) - a) / g.
+// None when a pool isn't V2 or the cycle loses money at any size.
+fn v2_optimal_amount_in(pools: &[Pool], token_in: H160) -> Option<f64> {
+    // Virtual reserves in and out, and the combined fee multiplier
+    let (mut a, mut b, mut g) = (0.0, 0.0, 1.0);
+    let mut token = token_in;
+
+    for (i, pool) in pools.iter().enumerate() {
+        let Pool::UniswapV2(v2_pool) = pool else {
+            return None;
+        };
+
+        let (reserve_in, reserve_out) = match token == v2_pool.token_a {
+            true => (v2_pool.reserve_0 as f64, v2_pool.reserve_1 as f64),
+            false => (v2_pool.reserve_1 as f64, v2_pool.reserve_0 as f64),
+        };
+        let gamma = 1.0 - fee_fraction(pool);
+
+        if i == 0 {
+            (a, b, g) = (reserve_in, reserve_out, gamma);
+        } else {
+            let denominator = reserve_in + gamma * b;
+            (a, b, g) = (
+                a * reserve_in * gamma / denominator,
+                b * reserve_out * gamma / denominator,
+                g * gamma,
+            );
+        }
+
+        token = pool_token_out(pool, token);
+    }
+
+    if g * b <= a || a <= 0.0 {
+        return None;
+    }
+
+    Some(((g * a * b).sqrt() - a) / g)
+}
+
+// Profit around a cycle is concave in the input for concentrated liquidity too,
+// so the best input is found by golden section search over the simulation
+pub async fn golden_section_search<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    max_amount_in: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> f64 {
+    let inverse_phi = (5_f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.0, u256_to_f64(max_amount_in));
+
+    let mut x1 = high - inverse_phi * (high - low);
+    let mut x2 = low + inverse_phi * (high - low);
+    let mut profit1 = profit_at(pools, token_in, x1, context, middleware.clone()).await;
+    let mut profit2 = profit_at(pools, token_in, x2, context, middleware.clone()).await;
+
+    while high - low >= 1.0 && high - low >= GOLDEN_SECTION_TOLERANCE * high {
+        if profit1 < profit2 {
+            low = x1;
+            (x1, profit1) = (x2, profit2);
+            x2 = low + inverse_phi * (high - low);
+            profit2 = profit_at(pools, token_in, x2, context, middleware.clone()).await;
+        } else {
+            high = x2;
+            (x2, profit2) = (x1, profit1);
+            x1 = high - inverse_phi * (high - low);
+            profit1 = profit_at(pools, token_in, x1, context, middleware.clone()).await;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+async fn profit_at<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    amount_in: f64,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> f64 {
+    let amount_in = f64_to_u256(amount_in);
+    if amount_in.is_zero() {
+        return 0.0;
+    }
+
+    match simulate_path_amounts(pools, token_in, amount_in, context, middleware).await {
+        Ok(amounts) => u256_to_f64(*amounts.last().unwrap()) - u256_to_f64(amount_in),
+        Err(_) => f64::MIN,
+    }
+}
@@ -1,17 +1,25 @@
 use cfmms::dex::{Dex, DexVariant};
 use clap::Parser;
 use ethers::{
+    providers::Middleware,
     // signers::LocalWallet,
-    types::{BlockNumber, H160},
+    types::{BlockNumber, H160, U256},
 };
 use serde::Deserialize;
-use std::{
-    // fs::read_to_string,
-    str::FromStr,
-    vec,
+use std::{collections::HashSet, fs::read_to_string, str::FromStr, sync::Arc, vec};
+
+use crate::{
+    error::ConfigError,
+    tokens::{TokenRegistry, TokenTag},
 };
 
-use crate::constants::*;
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+pub const DEFAULT_THROTTLE: usize = 10;
+pub const DEFAULT_MAX_HOPS: usize = 3;
+pub const DEFAULT_BEAM_WIDTH: usize = 8;
+pub const DEFAULT_MAX_NOTIONAL: f64 = 100.0;
+pub const DEFAULT_SPLIT_CHUNKS: usize = 10;
+pub const DEFAULT_SPLIT_PRICE_IMPACT: f64 = 0.01;
 
 #[derive(Parser, Default, Debug)]
 pub struct Args {
@@ -22,15 +30,119 @@ pub struct Args {
 #[derive(Debug, Deserialize)]
 pub struct Toml {
     pub chain_name: String,
-    pub http_endpoint: String,
-    pub ws_endpoint: String,
-    pub wallet_address: String,
-    pub private_key: String,
+    // Falls back to ETHEREUM_RPC_ENDPOINT / ETHEREUM_WS_ENDPOINT when omitted
+    pub http_endpoint: Option<String>,
+    pub ws_endpoint: Option<String>,
+    #[serde(default, rename = "dex")]
+    pub dexes: Vec<DexToml>,
+    // Max requests per second while syncing pools
+    #[serde(default = "default_throttle")]
+    pub throttle: usize,
+    // Tokens routes may hop through, the wrapped native token and stables when omitted
+    #[serde(default)]
+    pub intermediate_tokens: Vec<String>,
+    // Pairs of tokens that routes never swap between directly
+    #[serde(default)]
+    pub skip_pairs: Vec<[String; 2]>,
+    #[serde(default = "default_max_hops")]
+    pub max_hops: usize,
+    // Partial routes kept per hop when searching the token graph
+    #[serde(default = "default_beam_width")]
+    pub beam_width: usize,
+    // Pieces an order is cut into when splitting it across pools
+    #[serde(default = "default_split_chunks")]
+    pub split_chunks: usize,
+    // Price impact of the best single route above which an order is split
+    #[serde(default = "default_split_price_impact")]
+    pub split_price_impact: f64,
+    // Fixed gas price for route scoring, read from the endpoint when omitted
+    pub gas_price_gwei: Option<f64>,
+    // Largest arbitrage input, in whole units of the cycle's start token
+    #[serde(default = "default_max_notional")]
+    pub max_notional: f64,
+    // Token list json merged over the chain's built in tokens
+    pub token_list: Option<String>,
+    pub wallet_address: Option<String>,
+    pub private_key: Option<String>,
+    #[serde(default)]
     pub taxed_tokens: bool,
+    // amms checkpoint the markets sync resumes from and writes back
+    pub checkpoint_path: Option<String>,
+    // Uniswap V3 quoter for pools without tick data, the chain's deployment when omitted
+    pub v3_quoter_address: Option<String>,
+    #[serde(default)]
     pub order_cancellation: bool,
+    #[serde(default)]
     pub order_refresh: bool,
 }
 
+fn default_throttle() -> usize {
+    DEFAULT_THROTTLE
+}
+
+fn default_max_hops() -> usize {
+    DEFAULT_MAX_HOPS
+}
+
+fn default_beam_width() -> usize {
+    DEFAULT_BEAM_WIDTH
+}
+
+fn default_split_chunks() -> usize {
+    DEFAULT_SPLIT_CHUNKS
+}
+
+fn default_split_price_impact() -> f64 {
+    DEFAULT_SPLIT_PRICE_IMPACT
+}
+
+fn default_max_notional() -> f64 {
+    DEFAULT_MAX_NOTIONAL
+}
+
+// A `[[dex]]` table in the config file
+#[derive(Debug, Deserialize)]
+pub struct DexToml {
+    pub name: String,
+    pub factory_address: String,
+    // "UniswapV2" or "UniswapV3"
+    pub variant: String,
+    pub creation_block: u64,
+    // Pool fee in 1/100000 (300 = 0.3%), defaults to 300 for V2 forks
+    pub fee: Option<u64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl DexToml {
+    pub fn to_dex(&self) -> Result<Dex, ConfigError> {
+        let factory_address =
+            parse_address(&format!("dex {:?}", self.name), &self.factory_address)?;
+
+        let dex_variant = match self.variant.to_lowercase().as_str() {
+            "uniswapv2" => DexVariant::UniswapV2,
+            "uniswapv3" => DexVariant::UniswapV3,
+            _ => {
+                return Err(ConfigError::UnknownDexVariant(
+                    self.name.clone(),
+                    self.variant.clone(),
+                ))
+            }
+        };
+
+        Ok(Dex::new(
+            factory_address,
+            dex_variant,
+            self.creation_block,
+            self.fee,
+        ))
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub native_token: NativeToken,
@@ -44,7 +156,23 @@ pub struct Config {
     // pub wallet_address: H160,
     // pub wallet_key: LocalWallet,
     pub chain: Chain,
-    // pub taxed_tokens: bool,
+    pub throttle: usize,
+    pub intermediate_tokens: Vec<H160>,
+    pub skip_pairs: Vec<(H160, H160)>,
+    pub max_hops: usize,
+    pub beam_width: usize,
+    pub split_chunks: usize,
+    pub split_price_impact: f64,
+    // In wei
+    pub gas_price: Option<U256>,
+    pub max_notional: f64,
+    pub tokens: TokenRegistry,
+    // Detect transfer taxes of routed tokens
+    pub taxed_tokens: bool,
+    // Checkpoint the markets sync resumes from and writes back
+    pub checkpoint_path: Option<String>,
+    // None on chains without a Uniswap V3 quoter
+    pub v3_quoter: Option<H160>,
     // pub order_cancellation: bool,
     // pub order_refresh: bool,
 }
@@ -63,7 +191,19 @@ impl Default for Config {
             // wallet_address: H160::zero(),
             // wallet_key: LocalWallet::new(&mut rand::thread_rng()),
             chain: Chain::Ethereum,
-            // taxed_tokens: false,
+            throttle: DEFAULT_THROTTLE,
+            intermediate_tokens: vec![],
+            skip_pairs: vec![],
+            max_hops: DEFAULT_MAX_HOPS,
+            beam_width: DEFAULT_BEAM_WIDTH,
+            split_chunks: DEFAULT_SPLIT_CHUNKS,
+            split_price_impact: DEFAULT_SPLIT_PRICE_IMPACT,
+            gas_price: None,
+            max_notional: DEFAULT_MAX_NOTIONAL,
+            tokens: TokenRegistry::new(),
+            taxed_tokens: false,
+            checkpoint_path: None,
+            v3_quoter: None,
             // order_cancellation: false,
             // order_refresh: false,
         }
@@ -73,35 +213,40 @@ impl Default for Config {
 #[derive(Debug, Clone, Copy)]
 pub enum Chain {
     Ethereum,
-    // Polygon,
-    // Optimism,
-    // Arbitrum,
+    Polygon,
+    Optimism,
+    Arbitrum,
     Bsc,
+    Base,
     // Cronos,
 }
 
-impl Chain {
-    pub fn from_str(chain_name: &str) -> Chain {
+impl FromStr for Chain {
+    type Err = ConfigError;
+
+    fn from_str(chain_name: &str) -> Result<Chain, ConfigError> {
         match chain_name.to_lowercase().as_str() {
-            "ethereum" => Chain::Ethereum,
-            // "polygon" => Chain::Polygon,
-            // "optimism" => Chain::Optimism,
-            // "arbitrum" => Chain::Arbitrum,
-            "bsc" => Chain::Bsc,
-            // "cronos" => Chain::Cronos,
-            other => {
-                panic!("Unrecognized `chain_name`: {:?}", other)
-            }
+            "ethereum" => Ok(Chain::Ethereum),
+            "polygon" => Ok(Chain::Polygon),
+            "optimism" => Ok(Chain::Optimism),
+            "arbitrum" => Ok(Chain::Arbitrum),
+            "bsc" => Ok(Chain::Bsc),
+            "base" => Ok(Chain::Base),
+            // "cronos" => Ok(Chain::Cronos),
+            _ => Err(ConfigError::UnknownChain(chain_name.to_string())),
         }
     }
+}
 
+impl Chain {
     pub fn chain_id(&self) -> usize {
         match self {
             Chain::Ethereum => 1,
-            // Chain::Polygon => 137,
-            // Chain::Optimism => 420,
-            // Chain::Arbitrum => 42161,
+            Chain::Polygon => 137,
+            Chain::Optimism => 10,
+            Chain::Arbitrum => 42161,
             Chain::Bsc => 56,
+            Chain::Base => 8453,
             // Chain::Cronos => 25,
         }
     }
@@ -109,37 +254,183 @@ impl Chain {
     pub fn is_eip1559(&self) -> bool {
         match self {
             Chain::Ethereum => true,
-            // Chain::Polygon => true,
-            // Chain::Optimism => true,
-            // Chain::Arbitrum => true,
+            Chain::Polygon => true,
+            Chain::Optimism => true,
+            Chain::Arbitrum => true,
             Chain::Bsc => false,
+            Chain::Base => true,
             // Chain::Cronos => false,
         }
     }
+
+    pub fn native_token(&self) -> NativeToken {
+        match self {
+            Chain::Ethereum | Chain::Optimism | Chain::Arbitrum | Chain::Base => NativeToken::ETH,
+            Chain::Polygon => NativeToken::MATIC,
+            Chain::Bsc => NativeToken::BNB,
+        }
+    }
+
+    // Uniswap V3 QuoterV1, only deployed where Uniswap launched V3 before QuoterV2
+    pub fn v3_quoter(&self) -> Option<H160> {
+        match self {
+            Chain::Ethereum | Chain::Polygon | Chain::Optimism | Chain::Arbitrum => {
+                Some(H160::from_str("0xb27308f9F90D607463bb33eA1BeBb41C27CE5AB6").unwrap())
+            }
+            Chain::Bsc | Chain::Base => None,
+        }
+    }
+
+    // Dexes used when the config file has no `[[dex]]` tables
+    pub fn default_dexes(&self) -> Vec<Dex> {
+        match self {
+            Chain::Ethereum => vec![
+                // Sushiswap
+                Dex::new(
+                    H160::from_str("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac").unwrap(),
+                    DexVariant::UniswapV2,
+                    10794229,
+                    Some(300),
+                ),
+                // Uniswap V2
+                Dex::new(
+                    H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f").unwrap(),
+                    DexVariant::UniswapV2,
+                    10000835,
+                    Some(300),
+                ),
+                // Uniswap V3
+                Dex::new(
+                    H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
+                    DexVariant::UniswapV3,
+                    12369621,
+                    None,
+                ),
+            ],
+            Chain::Polygon => vec![
+                // Sushiswap
+                Dex::new(
+                    H160::from_str("0xc35DADB65012eC5796536bD9864eD8773aBc74C4").unwrap(),
+                    DexVariant::UniswapV2,
+                    11333218,
+                    Some(300),
+                ),
+                // Uniswap V3
+                Dex::new(
+                    H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
+                    DexVariant::UniswapV3,
+                    22757547,
+                    None,
+                ),
+                // Quickswap
+                Dex::new(
+                    H160::from_str("0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32").unwrap(),
+                    DexVariant::UniswapV2,
+                    4931780,
+                    Some(300),
+                ),
+            ],
+            Chain::Optimism => vec![
+                // Uniswap V3, predeployed at regenesis
+                Dex::new(
+                    H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
+                    DexVariant::UniswapV3,
+                    0,
+                    None,
+                ),
+            ],
+            Chain::Arbitrum => vec![
+                // Sushiswap
+                Dex::new(
+                    H160::from_str("0xc35DADB65012eC5796536bD9864eD8773aBc74C4").unwrap(),
+                    DexVariant::UniswapV2,
+                    70,
+                    Some(300),
+                ),
+                // Uniswap V3
+                Dex::new(
+                    H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
+                    DexVariant::UniswapV3,
+                    35,
+                    None,
+                ),
+                // Camelot
+                Dex::new(
+                    H160::from_str("0x6EcCab422D763aC031210895C81787E87B43A652").unwrap(),
+                    DexVariant::UniswapV2,
+                    20702,
+                    Some(300),
+                ),
+            ],
+            Chain::Bsc => vec![
+                // Pancakeswap v2
+                Dex::new(
+                    H160::from_str("0xca143ce32fe78f1f7019d7d551a6402fc5350c73").unwrap(),
+                    DexVariant::UniswapV2,
+                    6809737,
+                    Some(250),
+                ),
+                // Pancakeswap v1
+                Dex::new(
+                    H160::from_str("0xBCfCcbde45cE874adCB698cC183deBcF17952812").unwrap(),
+                    DexVariant::UniswapV2,
+                    586851,
+                    Some(250),
+                ),
+            ],
+            Chain::Base => vec![
+                // Uniswap V3
+                Dex::new(
+                    H160::from_str("0x33128a8fC17869897dcE68Ed026d694621f6FDfD").unwrap(),
+                    DexVariant::UniswapV3,
+                    1371680,
+                    None,
+                ),
+            ],
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum NativeToken {
     ETH,
-    // MATIC,
-    // BNB,
+    MATIC,
+    BNB,
     // CRO,
 }
 
 impl Config {
-    pub fn new(rpc_endpoint: String, ws_endpoint: String) -> Config {
-        // let args = Args::parse();
+    pub fn new(args: &Args) -> Result<Config, ConfigError> {
+        let path_to_config = args
+            .config
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
 
-        // let path_to_config = if args.config.is_some() {
-        //     args.config.unwrap()
-        // } else {
-        //     "./coex.toml".to_string()
-        // };
-        // let coex_toml: Toml =
-        //     toml::from_str(&read_to_string(path_to_config).expect("Could not read toml from path"))
-        //         .expect("Could not convert str to Config");
+        let coex_toml: Toml = toml::from_str(
+            &read_to_string(&path_to_config)
+                .map_err(|err| ConfigError::ReadError(path_to_config.clone(), err))?,
+        )
+        .map_err(|err| ConfigError::ParseError(path_to_config.clone(), err))?;
+
+        Config::from_toml(coex_toml)
+    }
 
-        let mut config = Config::default();
+    pub fn from_toml(coex_toml: Toml) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            chain: Chain::from_str(&coex_toml.chain_name)?,
+            throttle: coex_toml.throttle,
+            max_hops: coex_toml.max_hops,
+            beam_width: coex_toml.beam_width,
+            split_chunks: coex_toml.split_chunks,
+            split_price_impact: coex_toml.split_price_impact,
+            gas_price: coex_toml
+                .gas_price_gwei
+                .map(|gwei| U256::from((gwei * 1e9) as u128)),
+            max_notional: coex_toml.max_notional,
+            taxed_tokens: coex_toml.taxed_tokens,
+            checkpoint_path: coex_toml.checkpoint_path,
+            ..Default::default()
+        };
 
         // config.wallet_address =
         //     H160::from_str(&coex_toml.wallet_address).expect("Could not parse wallet address");
@@ -147,239 +438,109 @@ impl Config {
         //     .private_key
         //     .parse()
         //     .expect("Could not parse private key");
-        // config.taxed_tokens = coex_toml.taxed_tokens;
         // config.order_refresh = coex_toml.order_refresh;
         // config.order_cancellation = coex_toml.order_cancellation;
 
-        // let chain = Chain::from_str(&coex_toml.chain_name);
-        let chain = Chain::from_str("ethereum");
-        config.chain = chain;
-
-        match config.chain {
-            Chain::Ethereum => {
-                config.http_endpoint = rpc_endpoint;
-                config.ws_endpoint = ws_endpoint;
-                config.native_token = NativeToken::ETH;
-                config.weth_address = H160::from_str(WETH).unwrap();
-                config.weth_decimals = 18;
-                // config.executor_address =
-                //     H160::from_str("0x91AE75251Bc0c6654EF0B327D190877B49b21A2E").unwrap();
-                // limit order book creation block
-                config.protocol_creation_block = BlockNumber::Number(16616601.into());
-
-                config.dexes = vec![
-                    // Sushiswap
-                    Dex::new(
-                        H160::from_str(SUSHISWAP).unwrap(),
-                        DexVariant::UniswapV2,
-                        SUSHISWAP_CREATION_BLOCK,
-                        Some(300),
-                    ),
-                    // Uniswap V2
-                    Dex::new(
-                        H160::from_str(UNISWAP_V2).unwrap(),
-                        DexVariant::UniswapV2,
-                        UNISWAP_V2_CREATION_BLOCK,
-                        None,
-                    ),
-                    // Uniswap V3
-                    Dex::new(
-                        H160::from_str(UNISWAP_V3).unwrap(),
-                        DexVariant::UniswapV3,
-                        UNISWAP_V3_CREATION_BLOCK,
-                        None,
-                    ),
-                    // Pancakeswap
-                    Dex::new(
-                        H160::from_str("0x1097053Fd2ea711dad45caCcc45EfF7548fCB362").unwrap(),
-                        DexVariant::UniswapV2,
-                        15614590,
-                        Some(300),
-                    ),
-                    // // Shibaswap
-                    // Dex::new(
-                    //     H160::from_str("0x115934131916C8b277DD010Ee02de363c09d037c").unwrap(),
-                    //     DexVariant::UniswapV2,
-                    //     12771526,
-                    //     Some(300),
-                    // ),
-                ];
-            } // Chain::Polygon => {
-              //     config.http_endpoint = coex_toml.http_endpoint;
-              //     config.ws_endpoint = coex_toml.ws_endpoint;
-              //     config.native_token = NativeToken::MATIC;
-              //     config.weth_address =
-              //         H160::from_str("0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270").unwrap();
-              //     config.weth_decimals = 18;
-              //     config.limit_order_book =
-              //         H160::from_str("0xDe160A8fb9eB7bd2309E5470D9F0dB3Fc6C99E78").unwrap();
-              //     config.sandbox_limit_order_book =
-              //         H160::from_str("0x87b6Ba07aAB69AF8f91cc7372bBF589e28F5219d").unwrap();
-              //     config.sandbox_limit_order_router =
-              //         H160::from_str("0x1489c7ccb8da15ac6b4cc7e5548c4f3dd8a04ab9").unwrap();
-              //     config.executor_address =
-              //         H160::from_str("0x6d53e6b2c079a98fC0F736dFdE348278FDc91629").unwrap();
-              //         // limit order book creation block
-              //     config.protocol_creation_block = BlockNumber::Number(39229433.into());
-
-              //     config.dexes = vec![
-              //         // Sushiswap
-              //         Dex::new(
-              //             H160::from_str("0xc35DADB65012eC5796536bD9864eD8773aBc74C4").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             11333218,
-              //             Some(300),
-              //         ),
-              //         //UniswapV3
-              //         Dex::new(
-              //             H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
-              //             DexVariant::UniswapV3,
-              //             22757547,
-              //             None,
-              //         ),
-              //         // Quickswap
-              //         Dex::new(
-              //             H160::from_str("0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             4931780,
-              //             Some(300),
-              //         ),
-              //         //MM Finance
-              //         Dex::new(
-              //             H160::from_str("0x7cFB780010e9C861e03bCbC7AC12E013137D47A5").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             31337344,
-              //             Some(300),
-              //         ),
-              //         // //DFYN
-              //         Dex::new(
-              //             H160::from_str("0xE7Fb3e833eFE5F9c441105EB65Ef8b261266423B").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             5436831,
-              //             Some(300),
-              //         ),
-              //     ];
-              // }
-
-              // Chain::Arbitrum => {
-              //     config.http_endpoint = coex_toml.http_endpoint;
-              //     config.ws_endpoint = coex_toml.ws_endpoint;
-              //     config.native_token = NativeToken::ETH;
-              //     config.weth_address =
-              //         H160::from_str("0x82aF49447D8a07e3bd95BD0d56f35241523fBab1").unwrap();
-              //     config.weth_decimals = 18;
-              //     config.limit_order_book =
-              //         H160::from_str("0xf88F7Ebba40674Ce4364a048f6A72367979B7274").unwrap();
-              //     config.sandbox_limit_order_book =
-              //         H160::from_str("0xAAb2e639AaacE78047990B621aD939d4D73680De").unwrap();
-              //     config.sandbox_limit_order_router =
-              //         H160::from_str("0x2841a7f275266cc00a02f2c341d04b9b7bd4b056").unwrap();
-              //     config.executor_address =
-              //         H160::from_str("0xe56B8CF0aB1865Dd0C9A1c81C076D2843Eb90B97").unwrap();
-              //         // limit order book creation block
-              //     config.protocol_creation_block = BlockNumber::Number(71267.into());
-
-              //     config.dexes = vec![
-              //         // Sushiswap
-              //         Dex::new(
-              //             H160::from_str("0xc35DADB65012eC5796536bD9864eD8773aBc74C4").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             70,
-              //             Some(300),
-              //         ),
-              //         //UniswapV3
-              //         Dex::new(
-              //             H160::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap(),
-              //             DexVariant::UniswapV3,
-              //             35,
-              //             None,
-              //         ),
-              //         // Camelot
-              //         Dex::new(
-              //             H160::from_str("0x6EcCab422D763aC031210895C81787E87B43A652").unwrap(),
-              //             DexVariant::UniswapV2,
-              //             20702,
-              //             Some(300),
-              //         ),
-              //     ];
-
-              // }
-              Chain::Bsc => {
-                  config.http_endpoint = rpc_endpoint;
-                  config.ws_endpoint = ws_endpoint;
-                  config.native_token = NativeToken::ETH;
-                  config.weth_address =
-                      H160::from_str("0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c").unwrap();
-                  config.weth_decimals = 18;
-                //   config.limit_order_book =
-                //       H160::from_str("0x400966bC4ab862C2094d6d749DB0C42b66605F4A").unwrap();
-                //   config.sandbox_limit_order_book =
-                //       H160::from_str("0x4dCdBa96dc7244baa763eC51Ca0dBcDddBCee4e7").unwrap();
-                //   config.sandbox_limit_order_router =
-                //       H160::from_str("0x6a6e18b1a88d4b4a7af2135477aa5b7eee935dc3").unwrap();
-                //   config.executor_address =
-                //       H160::from_str("0x902c9e3202F5191db0B6edF5c038F4941Dfd6641").unwrap();
-                  config.protocol_creation_block = BlockNumber::Number(25617424.into());
-
-                  config.dexes = vec![
-                      // Pancakeswap v2
-                      Dex::new(
-                          H160::from_str("0xca143ce32fe78f1f7019d7d551a6402fc5350c73").unwrap(),
-                          DexVariant::UniswapV2,
-                          6809737,
-                          Some(250),
-                      ),
-                      // Pancakeswap v1
-                      Dex::new(
-                          H160::from_str("0xBCfCcbde45cE874adCB698cC183deBcF17952812").unwrap(),
-                          DexVariant::UniswapV2,
-                          586851,
-                          Some(250),
-                      ),
-                    //   // Apeswap
-                    //   Dex::new(
-                    //       H160::from_str("0x0841BD0B734E4F5853f0dD8d7Ea041c241fb0Da6").unwrap(),
-                    //       DexVariant::UniswapV2,
-                    //       4855901,
-                    //       Some(200),
-                    //   ),
-                    //   // Biswap
-                    //   Dex::new(
-                    //       H160::from_str("0x858E3312ed3A876947EA49d572A7C42DE08af7EE").unwrap(),
-                    //       DexVariant::UniswapV2,
-                    //       7664646,
-                    //       Some(100),
-                    //   ),
-                    //   // Babyswap
-                    //   Dex::new(
-                    //       H160::from_str("0x86407bEa2078ea5f5EB5A52B2caA963bC1F889Da").unwrap(),
-                    //       DexVariant::UniswapV2,
-                    //       7911393,
-                    //       Some(300),
-                    //   ),
-                    //    // Sushiswap
-                    //    Dex::new(
-                    //       H160::from_str("0xc35DADB65012eC5796536bD9864eD8773aBc74C4").unwrap(),
-                    //       DexVariant::UniswapV2,
-                    //       5205069,
-                    //       Some(300),
-                    //   ),
-                    //    // BabyDoge Swap
-                    //    Dex::new(
-                    //       H160::from_str("0x4693B62E5fc9c0a45F89D62e6300a03C85f43137").unwrap(),
-                    //       DexVariant::UniswapV2,
-                    //       18973559,
-                    //       Some(300),
-                    //   ),
-                  ];
-              }
-              // Chain::Cronos => {
-              //     todo!("Cronos configuration not yet implemented");
-              // }
-              // Chain::Optimism => {
-              //     todo!("Optimism configuration not yet implemented");
-              // }
+        config.http_endpoint = match coex_toml.http_endpoint {
+            Some(http_endpoint) => http_endpoint,
+            None => std::env::var("ETHEREUM_RPC_ENDPOINT").map_err(|_| {
+                ConfigError::MissingEndpoint("http_endpoint", "ETHEREUM_RPC_ENDPOINT")
+            })?,
+        };
+        config.ws_endpoint = match coex_toml.ws_endpoint {
+            Some(ws_endpoint) => ws_endpoint,
+            None => std::env::var("ETHEREUM_WS_ENDPOINT")
+                .map_err(|_| ConfigError::MissingEndpoint("ws_endpoint", "ETHEREUM_WS_ENDPOINT"))?,
+        };
+
+        for token in &coex_toml.intermediate_tokens {
+            config
+                .intermediate_tokens
+                .push(parse_address("intermediate token", token)?);
+        }
+
+        config.v3_quoter = match &coex_toml.v3_quoter_address {
+            Some(v3_quoter_address) => Some(parse_address("v3 quoter", v3_quoter_address)?),
+            None => config.chain.v3_quoter(),
+        };
+
+        for [token_a, token_b] in &coex_toml.skip_pairs {
+            config.skip_pairs.push((
+                parse_address("skip pair", token_a)?,
+                parse_address("skip pair", token_b)?,
+            ));
         }
-        config
+
+        if coex_toml.dexes.is_empty() {
+            config.dexes = config.chain.default_dexes();
+        } else {
+            let mut factories = HashSet::new();
+            for dex in coex_toml.dexes.iter().filter(|dex| dex.enabled) {
+                let dex = dex.to_dex()?;
+                if !factories.insert(dex.factory_address()) {
+                    return Err(ConfigError::DuplicateDex(dex.factory_address()));
+                }
+                config.dexes.push(dex);
+            }
+        }
+
+        config.tokens = TokenRegistry::for_chain(config.chain);
+        if let Some(token_list) = &coex_toml.token_list {
+            config.tokens.extend(TokenRegistry::from_token_list(
+                token_list,
+                config.chain.chain_id(),
+            )?);
+        }
+
+        let wrapped_native = config
+            .tokens
+            .wrapped_native()
+            .ok_or(ConfigError::MissingWrappedNative)?;
+        if config.intermediate_tokens.is_empty() {
+            let mut connectors: Vec<H160> = config
+                .tokens
+                .with_tag(TokenTag::Stable)
+                .map(|token| token.address)
+                .collect();
+            connectors.sort();
+            connectors.insert(0, wrapped_native.address);
+            config.intermediate_tokens = connectors;
+        }
+
+        config.native_token = config.chain.native_token();
+        config.weth_address = wrapped_native.address;
+        config.weth_decimals = wrapped_native.decimals;
+        // limit order book creation block
+        config.protocol_creation_block = match config.chain {
+            Chain::Ethereum => BlockNumber::Number(16616601.into()),
+            Chain::Polygon => BlockNumber::Number(39229433.into()),
+            Chain::Arbitrum => BlockNumber::Number(71267.into()),
+            Chain::Bsc => BlockNumber::Number(25617424.into()),
+            Chain::Optimism | Chain::Base => BlockNumber::Latest,
+        };
+
+        Ok(config)
+    }
+
+    // Checks that the http endpoint is on the configured chain
+    pub async fn validate<M: Middleware>(&self, middleware: Arc<M>) -> Result<(), ConfigError> {
+        let chain_id = middleware
+            .get_chainid()
+            .await
+            .map_err(|err| ConfigError::ChainIdUnavailable(err.to_string()))?;
+
+        if chain_id != U256::from(self.chain.chain_id()) {
+            return Err(ConfigError::ChainIdMismatch {
+                expected: self.chain.chain_id(),
+                actual: chain_id,
+            });
+        }
+
+        Ok(())
     }
 }
+
+fn parse_address(field: &str, address: &str) -> Result<H160, ConfigError> {
+    H160::from_str(address)
+        .map_err(|_| ConfigError::InvalidAddress(field.to_string(), address.to_string()))
+}
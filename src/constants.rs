@@ -2,23 +2,17 @@ pub const NULL_ADDRESS: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
 
 pub const ETH: &str = "0x0000000000000000000000000000000000000000";
 pub const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
-pub const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
-pub const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
 
-pub const PANCAKESWAP_V2: &str = "0x1097053Fd2ea711dad45caCcc45EfF7548fCB362";
-pub const PANCAKESWAP_CREATION_BLOCK: u64 = 15614590;
-pub const SUSHISWAP: &str = "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac";
-pub const SUSHISWAP_CREATION_BLOCK: u64 = 10794229;
-pub const SUSHISWAP_FEE: u32 = 300;
-pub const UNISWAP_V2: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
-pub const UNISWAP_V2_CREATION_BLOCK: u64 = 10000835;
 pub const UNISWAP_V2_FEE: u32 = 300;
-pub const UNISWAP_V3: &str = "0x1F98431c8aD98523631AE4a59f267346ea31F984";
-pub const UNISWAP_V3_CREATION_BLOCK: u64 = 12369621;
-pub const UNISWAP_V3_FEE: u32 = 185;
+
+// The protocol keeps 1 / PROTOCOL_FEE_DIVISOR of native input
+pub const PROTOCOL_FEE_DIVISOR: u64 = 100;
+
+// Rough gas used by one swap through each pool type
+pub const UNISWAP_V2_SWAP_GAS: u64 = 90_000;
+pub const UNISWAP_V3_SWAP_GAS: u64 = 140_000;
 
 pub const WETH_USDC_V2: &str = "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc";
-pub const V3_QUOTER_ADDRESS: &str = "0xb27308f9F90D607463bb33eA1BeBb41C27CE5AB6";
 
 pub const FIFTH_WEB_MULTICALL: &str = "0x83E082f589bA40d198D924c5A31788C1fc414e00";
 pub const FIFTH_WEB_ROUTER_V1: &str = "0x1c2bfabd93ccfa8a9206194702366524265134c0";
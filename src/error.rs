@@ -2,11 +2,13 @@ use cfmms::errors::CFMMError;
 use ethers::{
     prelude::{nonce_manager::NonceManagerError, AbiError, ContractError},
     providers::{Middleware, ProviderError},
-    types::{H160, H256},
+    types::{H160, H256, U256},
 };
 use thiserror::Error;
 use tokio::task::JoinError;
 
+use crate::routing::route::PoolDiagnostic;
+
 #[derive(Error, Debug)]
 pub enum ExecutorError<M>
 where
@@ -36,4 +38,54 @@ where
     MarketDoesNotExistForPair(H160, H160),
     #[error("Eth ABI error")]
     EthABIError(#[from] ethers::abi::Error),
+    #[error("Uniswap V3 math error")]
+    UniswapV3MathError(#[from] uniswap_v3_math::error::UniswapV3MathError),
+    #[error("No pool produced output from {0:?} to {1:?}")]
+    NoViableRoute(H160, H160, Vec<PoolDiagnostic>),
+    #[error("Checkpoint error")]
+    CheckpointError(#[from] CheckpointError),
+    #[error("V3 pool {0:?} has no tick data and the chain has no quoter")]
+    NoV3Quoter(H160),
+    #[error("No pools to keep in sync")]
+    NoTrackedPools,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read config file {0:?}")]
+    ReadError(String, #[source] std::io::Error),
+    #[error("Could not parse config file {0:?}")]
+    ParseError(String, #[source] toml::de::Error),
+    #[error("Could not parse token list {0:?}")]
+    TokenListParseError(String, #[source] serde_json::Error),
+    #[error("Unrecognized chain name {0:?}")]
+    UnknownChain(String),
+    #[error("Invalid address {1:?} for {0}")]
+    InvalidAddress(String, String),
+    #[error("Unrecognized variant {1:?} for dex {0:?}")]
+    UnknownDexVariant(String, String),
+    #[error("Token list {0:?} tags both {1:?} and {2:?} as the wrapped native token")]
+    DuplicateWrappedNative(String, H160, H160),
+    #[error("No wrapped native token in the token registry")]
+    MissingWrappedNative,
+    #[error("Dex factory {0:?} is configured more than once")]
+    DuplicateDex(H160),
+    #[error("Missing endpoint, set `{0}` in the config file or {1}")]
+    MissingEndpoint(&'static str, &'static str),
+    #[error("Could not get the chain id from the endpoint: {0}")]
+    ChainIdUnavailable(String),
+    #[error("Config is for chain id {expected} but the endpoint is on chain id {actual}")]
+    ChainIdMismatch { expected: usize, actual: U256 },
+}
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Could not read checkpoint {0:?}")]
+    ReadError(String, #[source] std::io::Error),
+    #[error("Could not parse checkpoint {0:?}")]
+    ParseError(String, #[source] serde_json::Error),
+    #[error("Could not serialize checkpoint {0:?}")]
+    SerializeError(String, #[source] serde_json::Error),
+    #[error("Could not write checkpoint {0:?}")]
+    WriteError(String, #[source] std::io::Error),
 }
@@ -1,21 +1,34 @@
 use std::{str::FromStr, sync::Arc};
 
+use clap::Parser;
 use dotenv::dotenv;
 use ethers::{
-    providers::{Http, Provider},
+    providers::{Http, Middleware, Provider},
     types::{H160, U256},
 };
+use tokio::sync::RwLock;
 
 pub mod abi;
+pub mod arbitrage;
 pub mod config;
 pub mod constants;
 pub mod error;
 pub mod markets;
 pub mod routing;
 pub mod tests;
+pub mod tokens;
 pub mod transactions;
 
-use crate::transactions::swap_transaction_calldata;
+use crate::{
+    markets::{
+        get_all_markets,
+        state_sync::{run_state_sync, PoolState},
+        store::MarketStore,
+    },
+    routing::RouteOptions,
+    tokens::transfer_tax::detect_transfer_taxes,
+    transactions::{swap_transaction_calldata, types::SwapRequest},
+};
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -23,14 +36,13 @@ async fn main() -> eyre::Result<()> {
 
     tracing_subscriber::fmt::init();
 
-    let rpc_endpoint: String = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
-    let ws_endpoint: String = std::env::var("ETHEREUM_WS_ENDPOINT")?;
-
-    // Initialize a new configuration
-    let configuration = config::Config::new(rpc_endpoint, ws_endpoint);
+    // Initialize a new configuration from the config file passed with --config
+    let args = config::Args::parse();
+    let mut configuration = config::Config::new(&args)?;
     let middleware = Arc::new(Provider::<Http>::try_from(
         configuration.http_endpoint.clone(),
     )?);
+    configuration.validate(middleware.clone()).await?;
 
     let token_in = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f").unwrap();
     let token_out = H160::from_str("0x9f8F72aA9304c8B593d555F12eF6589cC3A579A2").unwrap();
@@ -38,23 +50,72 @@ async fn main() -> eyre::Result<()> {
     let amount_in = U256::from_dec_str("1000000000").unwrap();
     let slippage: u32 = 10 * 100; // Should be permyriad value
     let receiver = H160::from_str("0x0000000000000000000000000000000000000000").unwrap();
+    let route_options = RouteOptions::from_config(&configuration).with_connector(token_x);
 
-    let (swap_data, swap_multicall) = swap_transaction_calldata(
-        &configuration,
+    let transfer_taxes = detect_transfer_taxes(
+        &mut configuration,
+        &[token_in, token_out, token_x],
+        middleware.clone(),
+    )
+    .await?;
+    for (token, tax) in transfer_taxes {
+        tracing::info!("Transfer tax of {:?}: {:?}", token, tax);
+    }
+
+    // Routing reads the synced pools from the store and only fetches pairs it doesn't hold. Pools
+    // are synced from this block on, the state sync catches up on anything after it.
+    let synced_block = middleware.get_block_number().await?.as_u64();
+    let pools = get_all_markets(
+        configuration.dexes.clone(),
+        configuration.throttle,
+        configuration.checkpoint_path.as_deref(),
+        middleware.clone(),
+    )
+    .await?;
+    let pool_state = Arc::new(RwLock::new(PoolState::new(
+        MarketStore::from_pools(pools),
+        synced_block,
+    )));
+
+    let state_sync = tokio::spawn({
+        let ws_endpoint = configuration.ws_endpoint.clone();
+        let pool_state = pool_state.clone();
+        async move { run_state_sync(&ws_endpoint, pool_state).await }
+    });
+
+    let request = SwapRequest {
         token_in,
         token_out,
-        token_x,
-        amount_in,
+        amount: amount_in,
         slippage,
         receiver,
-        middleware,
-    )
-    .await?;
+    };
+
+    let (swap_data, swap_multicall) = {
+        let mut pool_state = pool_state.write().await;
+        let PoolState {
+            market_store,
+            tick_store,
+            ..
+        } = &mut *pool_state;
+        swap_transaction_calldata(
+            &configuration,
+            &request,
+            &route_options,
+            tick_store,
+            market_store,
+            middleware,
+        )
+        .await?
+    };
 
     println!(
         "SwapData: {:?}\n\nSwapMultiCall: {:?}",
         swap_data, swap_multicall
     );
 
+    // The pools stay live until the subscription ends
+    state_sync.await??;
+
     Ok(())
 }
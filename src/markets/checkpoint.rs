@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string, rename, write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use amms::{
+    amm::{
+        factory::{AutomatedMarketMakerFactory, Factory},
+        uniswap_v2::{self, factory::UniswapV2Factory},
+        uniswap_v3::{self, factory::UniswapV3Factory},
+        AMM,
+    },
+    sync::checkpoint::Checkpoint,
+};
+use cfmms::{
+    dex::Dex,
+    pool::{Pool, UniswapV2Pool, UniswapV3Pool},
+};
+use ethers::types::{BlockNumber, H160, U256};
+
+use crate::{
+    error::CheckpointError,
+    markets::{
+        store::MarketStore,
+        uniswap_v3::{TickData, TickStore},
+    },
+};
+
+// Pools of one or more amms checkpoints, with the tick data of the V3 pools so that every pool
+// can be quoted without an endpoint
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointPools {
+    // Oldest block among the loaded checkpoints
+    pub block_number: u64,
+    // Factories whose pools the checkpoints hold
+    pub factories: Vec<H160>,
+    pub pools: Vec<Pool>,
+    pub tick_store: TickStore,
+}
+
+impl CheckpointPools {
+    pub fn market_store(&self) -> MarketStore {
+        MarketStore::from_pools(self.pools.clone())
+    }
+
+    // The block is the oldest among the checkpoints holding pools, one without pools says nothing
+    // about how fresh the others are
+    fn extend(&mut self, other: CheckpointPools) {
+        self.block_number = match (self.pools.is_empty(), other.pools.is_empty()) {
+            (_, true) => self.block_number,
+            (true, false) => other.block_number,
+            (false, false) => self.block_number.min(other.block_number),
+        };
+        self.factories.extend(other.factories);
+        self.pools.extend(other.pools);
+        self.tick_store.extend(other.tick_store);
+    }
+}
+
+fn v2_pool(pool: &uniswap_v2::UniswapV2Pool) -> Pool {
+    Pool::UniswapV2(UniswapV2Pool::new(
+        pool.address,
+        pool.token_a,
+        pool.token_a_decimals,
+        pool.token_b,
+        pool.token_b_decimals,
+        pool.reserve_0,
+        pool.reserve_1,
+        pool.fee,
+    ))
+}
+
+// The pool's tick data is None when the checkpoint was written without it. Zero words only mark
+// the edges of a partial read, see `amm`.
+fn v3_pool(pool: &uniswap_v3::UniswapV3Pool, block_number: u64) -> (Pool, Option<TickData>) {
+    let tick_data = match pool.tick_bitmap.is_empty() {
+        true => None,
+        false => Some(TickData {
+            block_number,
+            tick_spacing: pool.tick_spacing,
+            tick_bitmap: pool
+                .tick_bitmap
+                .iter()
+                .filter(|(_, word)| !word.is_zero())
+                .map(|(word_position, word)| (*word_position, *word))
+                .collect(),
+            ticks: pool
+                .ticks
+                .iter()
+                .filter(|(_, info)| info.initialized)
+                .map(|(tick, info)| (*tick, info.liquidity_net))
+                .collect(),
+            word_range: match pool.tick_bitmap.values().any(|word| word.is_zero()) {
+                true => pool
+                    .tick_bitmap
+                    .keys()
+                    .min()
+                    .zip(pool.tick_bitmap.keys().max())
+                    .map(|(min_word, max_word)| (*min_word, *max_word)),
+                false => None,
+            },
+        }),
+    };
+
+    let liquidity_net = pool
+        .ticks
+        .get(&pool.tick)
+        .map(|info| info.liquidity_net)
+        .unwrap_or(0);
+
+    (
+        Pool::UniswapV3(UniswapV3Pool::new(
+            pool.address,
+            pool.token_a,
+            pool.token_a_decimals,
+            pool.token_b,
+            pool.token_b_decimals,
+            pool.fee,
+            pool.liquidity,
+            pool.sqrt_price,
+            pool.tick,
+            pool.tick_spacing,
+            liquidity_net,
+        )),
+        tick_data,
+    )
+}
+
+pub fn dex_factory(dex: &Dex) -> Factory {
+    let creation_block = match dex.creation_block() {
+        BlockNumber::Number(number) => number.as_u64(),
+        _ => 0,
+    };
+
+    match dex {
+        Dex::UniswapV2(uniswap_v2_dex) => Factory::UniswapV2Factory(UniswapV2Factory::new(
+            uniswap_v2_dex.factory_address,
+            creation_block,
+            uniswap_v2_dex.fee as u32,
+        )),
+        Dex::UniswapV3(uniswap_v3_dex) => Factory::UniswapV3Factory(UniswapV3Factory::new(
+            uniswap_v3_dex.factory_address,
+            creation_block,
+        )),
+    }
+}
+
+// V3 pools are written with their tick data when the store has it. The amms format has no word
+// range, a partial read is written with zero words at both of its edges. The liquidity gross isn't
+// kept, the liquidity net's magnitude stands in for it.
+fn amm(pool: &Pool, tick_store: &TickStore) -> AMM {
+    match pool {
+        Pool::UniswapV2(pool) => AMM::UniswapV2Pool(uniswap_v2::UniswapV2Pool {
+            address: pool.address,
+            token_a: pool.token_a,
+            token_a_decimals: pool.token_a_decimals,
+            token_b: pool.token_b,
+            token_b_decimals: pool.token_b_decimals,
+            reserve_0: pool.reserve_0,
+            reserve_1: pool.reserve_1,
+            fee: pool.fee,
+        }),
+        Pool::UniswapV3(pool) => AMM::UniswapV3Pool(uniswap_v3::UniswapV3Pool {
+            address: pool.address,
+            token_a: pool.token_a,
+            token_a_decimals: pool.token_a_decimals,
+            token_b: pool.token_b,
+            token_b_decimals: pool.token_b_decimals,
+            liquidity: pool.liquidity,
+            sqrt_price: pool.sqrt_price,
+            fee: pool.fee,
+            tick: pool.tick,
+            tick_spacing: pool.tick_spacing,
+            tick_bitmap: tick_store
+                .get(pool.address)
+                .map(amm_tick_bitmap)
+                .unwrap_or_default(),
+            ticks: tick_store
+                .get(pool.address)
+                .map(|tick_data| {
+                    tick_data
+                        .ticks
+                        .iter()
+                        .map(|(tick, liquidity_net)| {
+                            let info = uniswap_v3::Info::new(
+                                liquidity_net.unsigned_abs(),
+                                *liquidity_net,
+                                true,
+                            );
+                            (*tick, info)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }),
+    }
+}
+
+fn amm_tick_bitmap(tick_data: &TickData) -> HashMap<i16, U256> {
+    let mut tick_bitmap = tick_data.tick_bitmap.clone();
+    if let Some((min_word, max_word)) = tick_data.word_range {
+        tick_bitmap.entry(min_word).or_default();
+        tick_bitmap.entry(max_word).or_default();
+    }
+    tick_bitmap
+}
+
+// Writes the pools in the amms checkpoint format. The file is replaced in one step so an
+// interrupted write never leaves a truncated checkpoint behind.
+pub fn write_checkpoint(
+    path: &str,
+    dexes: &[Dex],
+    pools: &[Pool],
+    tick_store: &TickStore,
+    block_number: u64,
+) -> Result<(), CheckpointError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as usize)
+        .unwrap_or(0);
+    let checkpoint = Checkpoint::new(
+        timestamp,
+        block_number,
+        dexes.iter().map(dex_factory).collect(),
+        pools.iter().map(|pool| amm(pool, tick_store)).collect(),
+    );
+
+    let json = serde_json::to_string_pretty(&checkpoint)
+        .map_err(|err| CheckpointError::SerializeError(path.to_string(), err))?;
+    let tmp_path = format!("{path}.tmp");
+    write(&tmp_path, json).map_err(|err| CheckpointError::WriteError(path.to_string(), err))?;
+    rename(&tmp_path, path).map_err(|err| CheckpointError::WriteError(path.to_string(), err))
+}
+
+// Reads a checkpoint written by amms sync. ERC4626 vaults have no pool model here and are skipped.
+pub fn load_checkpoint(path: &str) -> Result<CheckpointPools, CheckpointError> {
+    let checkpoint: Checkpoint = serde_json::from_str(
+        &read_to_string(path).map_err(|err| CheckpointError::ReadError(path.to_string(), err))?,
+    )
+    .map_err(|err| CheckpointError::ParseError(path.to_string(), err))?;
+
+    let mut checkpoint_pools = CheckpointPools {
+        block_number: checkpoint.block_number,
+        factories: checkpoint
+            .factories
+            .iter()
+            .map(|factory| factory.address())
+            .collect(),
+        ..Default::default()
+    };
+    for amm in &checkpoint.amms {
+        match amm {
+            AMM::UniswapV2Pool(pool) => checkpoint_pools.pools.push(v2_pool(pool)),
+            AMM::UniswapV3Pool(pool) => {
+                let (pool, tick_data) = v3_pool(pool, checkpoint.block_number);
+                if let Some(tick_data) = tick_data {
+                    checkpoint_pools
+                        .tick_store
+                        .insert(pool.address(), tick_data);
+                }
+                checkpoint_pools.pools.push(pool);
+            }
+            AMM::ERC4626Vault(_) => {}
+        }
+    }
+
+    Ok(checkpoint_pools)
+}
+
+// Every `.json` checkpoint in the directory, merged. Empty files stand for dexes that haven't been
+// synced yet and are skipped.
+pub fn load_checkpoints(dir: &str) -> Result<CheckpointPools, CheckpointError> {
+    let entries = read_dir(dir).map_err(|err| CheckpointError::ReadError(dir.to_string(), err))?;
+
+    let mut paths = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|err| CheckpointError::ReadError(dir.to_string(), err))?;
+        let path = entry.path();
+        let is_empty = entry
+            .metadata()
+            .map_err(|err| CheckpointError::ReadError(path.to_string_lossy().to_string(), err))?
+            .len()
+            == 0;
+        if !is_empty
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut checkpoint_pools = CheckpointPools::default();
+    for path in paths {
+        checkpoint_pools.extend(load_checkpoint(&path.to_string_lossy())?);
+    }
+
+    Ok(checkpoint_pools)
+}
@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use cfmms::pool::Pool;
+use ethers::types::{H160, H256, U256};
+
+use crate::markets::store::MarketStore;
+
+// Blocks kept for rollback, state older than this is treated as final
+pub const DEFAULT_REORG_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct JournalEntry {
+    block_number: u64,
+    block_hash: H256,
+    // State of each pool before this block first changed it
+    previous: HashMap<H160, Pool>,
+    // Indexes of the block's logs already applied
+    log_indexes: HashSet<U256>,
+}
+
+// Undo log of the pool changes of the most recent blocks, used to roll the store back when the
+// chain reorganizes
+#[derive(Debug, Clone)]
+pub struct StateJournal {
+    entries: VecDeque<JournalEntry>,
+    max_depth: usize,
+    last_safe_block: u64,
+}
+
+impl StateJournal {
+    // `safe_block` is the block the store's state was synced at
+    pub fn new(safe_block: u64, max_depth: usize) -> StateJournal {
+        StateJournal {
+            entries: VecDeque::new(),
+            max_depth: max_depth.max(1),
+            last_safe_block: safe_block,
+        }
+    }
+
+    // Newest block whose state can no longer be rolled back
+    pub fn last_safe_block(&self) -> u64 {
+        self.last_safe_block
+    }
+
+    // Newest journaled block
+    pub fn head(&self) -> Option<(u64, H256)> {
+        self.entries
+            .back()
+            .map(|entry| (entry.block_number, entry.block_hash))
+    }
+
+    pub fn block_hash(&self, block_number: u64) -> Option<H256> {
+        self.entries
+            .iter()
+            .find(|entry| entry.block_number == block_number)
+            .map(|entry| entry.block_hash)
+    }
+
+    // Whether the log at `log_index` of the block was applied since the block was journaled
+    pub fn is_applied(&self, block_hash: H256, log_index: U256) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.block_hash == block_hash && entry.log_indexes.contains(&log_index))
+    }
+
+    // Keeps the state of `pool` from before the block, only its first change in a block is kept
+    pub fn record(
+        &mut self,
+        block_number: u64,
+        block_hash: H256,
+        log_index: Option<U256>,
+        pool: Pool,
+    ) {
+        let is_head = self
+            .entries
+            .back()
+            .is_some_and(|entry| entry.block_hash == block_hash);
+        if !is_head {
+            self.entries.push_back(JournalEntry {
+                block_number,
+                block_hash,
+                previous: HashMap::new(),
+                log_indexes: HashSet::new(),
+            });
+        }
+
+        let entry = self.entries.back_mut().unwrap();
+        entry.previous.entry(pool.address()).or_insert(pool);
+        entry.log_indexes.extend(log_index);
+
+        self.advance(block_number);
+    }
+
+    // Makes the blocks `max_depth` or more below `head_block` final, whether or not they changed
+    // a pool. The depth is counted in blocks, blocks without pool changes have no entry.
+    pub fn advance(&mut self, head_block: u64) {
+        let safe_block = head_block.saturating_sub(self.max_depth as u64);
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.block_number <= safe_block)
+        {
+            self.entries.pop_front();
+        }
+        self.last_safe_block = self.last_safe_block.max(safe_block);
+    }
+
+    // Restores the pools changed in `block_number` and every later block, returns the first block
+    // rolled back or None when nothing was journaled from there
+    pub fn rollback(&mut self, market_store: &mut MarketStore, block_number: u64) -> Option<u64> {
+        let mut first_block = None;
+        while let Some(entry) = self.entries.back() {
+            if entry.block_number < block_number {
+                break;
+            }
+
+            let entry = self.entries.pop_back().unwrap();
+            for pool in entry.previous.into_values() {
+                market_store.insert(pool);
+            }
+            first_block = Some(entry.block_number);
+        }
+        first_block
+    }
+
+    // Rolls back the block with `block_hash` and every later block
+    pub fn rollback_block_hash(
+        &mut self,
+        market_store: &mut MarketStore,
+        block_hash: H256,
+    ) -> Option<u64> {
+        let block_number = self
+            .entries
+            .iter()
+            .find(|entry| entry.block_hash == block_hash)?
+            .block_number;
+        self.rollback(market_store, block_number)
+    }
+
+    // Rolls back the parent of a new block when the journal has it under another hash
+    pub fn check_parent(
+        &mut self,
+        market_store: &mut MarketStore,
+        block_number: u64,
+        parent_hash: H256,
+    ) -> Option<u64> {
+        let parent_number = block_number.checked_sub(1)?;
+        match self.block_hash(parent_number) {
+            Some(block_hash) if block_hash != parent_hash => {
+                self.rollback(market_store, parent_number)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for StateJournal {
+    fn default() -> StateJournal {
+        StateJournal::new(0, DEFAULT_REORG_DEPTH)
+    }
+}
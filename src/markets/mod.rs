@@ -1,9 +1,9 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}, panic::resume_unwind};
+use std::{collections::{HashMap, HashSet}, fs::metadata, sync::{Arc, Mutex}, panic::resume_unwind};
 
-use cfmms::{checkpoint, dex::Dex, errors::CFMMError, pool::Pool, throttle::RequestThrottle};
+use cfmms::{dex::Dex, errors::CFMMError, pool::{Pool, UniswapV2Pool}, throttle::RequestThrottle};
 use ethers::{
     providers::Middleware,
-    types::{H160, U256},
+    types::{BlockNumber, Filter, H160, U256},
     utils::keccak256,
 };
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
@@ -12,12 +12,27 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 // use AllPools::get_pools;
 
 use crate::{
+    abi::IUniswapV3Pool,
     error::ExecutorError,
-    markets
+    markets::{self, uniswap_v3::TickStore},
 };
 
+pub mod checkpoint;
+pub mod journal;
+pub mod state_sync;
+pub mod store;
+pub mod uniswap_v3;
+
 pub type Market = HashMap<H160, Pool>;
 
+// Blocks per request when looking for pools created since a checkpoint
+const POOL_CREATED_LOG_STEP: usize = 100000;
+// Blocks per request when catching the checkpoint's pools up, halved while the provider refuses
+// the request and doubled back after each request it takes
+const STATE_LOG_STEP: u64 = 10000;
+// Pools per request when catching the checkpoint's pools up
+const STATE_LOG_ADDRESSES: usize = 500;
+
 pub fn get_market_id(token_a: H160, token_b: H160) -> U256 {
     if token_a > token_b {
         U256::from_little_endian(&keccak256(
@@ -30,6 +45,190 @@ pub fn get_market_id(token_a: H160, token_b: H160) -> U256 {
     }
 }
 
+// Returns (token_a, token_b) of the pool
+pub fn pool_tokens(pool: &Pool) -> (H160, H160) {
+    match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => (uniswap_v2_pool.token_a, uniswap_v2_pool.token_b),
+        Pool::UniswapV3(uniswap_v3_pool) => (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b),
+    }
+}
+
+// Returns the other token of the pool
+pub fn pool_token_out(pool: &Pool, token_in: H160) -> H160 {
+    let (token_a, token_b) = pool_tokens(pool);
+    if token_in == token_a {
+        token_b
+    } else {
+        token_a
+    }
+}
+
+// Returns the decimals of (token_in, token_out) for a swap through the pool
+pub fn pool_decimals(pool: &Pool, token_in: H160) -> (u8, u8) {
+    let (token_a, decimals_a, decimals_b) = match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => (
+            uniswap_v2_pool.token_a,
+            uniswap_v2_pool.token_a_decimals,
+            uniswap_v2_pool.token_b_decimals,
+        ),
+        Pool::UniswapV3(uniswap_v3_pool) => (
+            uniswap_v3_pool.token_a,
+            uniswap_v3_pool.token_a_decimals,
+            uniswap_v3_pool.token_b_decimals,
+        ),
+    };
+
+    if token_in == token_a {
+        (decimals_a, decimals_b)
+    } else {
+        (decimals_b, decimals_a)
+    }
+}
+
+pub fn pool_has_liquidity(pool: &Pool) -> bool {
+    match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => {
+            uniswap_v2_pool.reserve_0 != 0 && uniswap_v2_pool.reserve_1 != 0
+        }
+        Pool::UniswapV3(uniswap_v3_pool) => {
+            uniswap_v3_pool.liquidity != 0 && !uniswap_v3_pool.sqrt_price.is_zero()
+        }
+    }
+}
+
+// UniswapV2Library.getAmountOut with the pool's fee, cfmms always takes 0.3%
+pub fn v2_amount_out(pool: &UniswapV2Pool, token_in: H160, amount_in: U256) -> U256 {
+    let (reserve_in, reserve_out) = match token_in == pool.token_a {
+        true => (U256::from(pool.reserve_0), U256::from(pool.reserve_1)),
+        false => (U256::from(pool.reserve_1), U256::from(pool.reserve_0)),
+    };
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return U256::zero();
+    }
+
+    let amount_in_with_fee = amount_in * (100_000 - pool.fee);
+    amount_in_with_fee * reserve_out / (reserve_in * 100_000 + amount_in_with_fee)
+}
+
+// UniswapV2Library.getAmountIn with the pool's fee, None when the reserves can't cover `amount_out`
+pub fn v2_amount_in(pool: &UniswapV2Pool, token_in: H160, amount_out: U256) -> Option<U256> {
+    let (reserve_in, reserve_out) = match token_in == pool.token_a {
+        true => (U256::from(pool.reserve_0), U256::from(pool.reserve_1)),
+        false => (U256::from(pool.reserve_1), U256::from(pool.reserve_0)),
+    };
+    if reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+
+    let numerator = reserve_in * amount_out * 100_000;
+    let denominator = (reserve_out - amount_out) * (100_000 - pool.fee);
+    Some(numerator / denominator + 1)
+}
+
+// Reserve of `token` in the pool, virtual reserves for V3
+pub fn pool_depth(pool: &Pool, token: H160) -> f64 {
+    let (reserve_a, reserve_b) = match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => (uniswap_v2_pool.reserve_0, uniswap_v2_pool.reserve_1),
+        Pool::UniswapV3(uniswap_v3_pool) => {
+            if !pool_has_liquidity(pool) {
+                return 0.0;
+            }
+            uniswap_v3_pool.calculate_virtual_reserves().unwrap_or((0, 0))
+        }
+    };
+
+    match token == pool_tokens(pool).0 {
+        true => reserve_a as f64,
+        false => reserve_b as f64,
+    }
+}
+
+// Lossy conversion for ranking and price math, U256 limbs are little endian
+pub fn u256_to_f64(amount: U256) -> f64 {
+    amount
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2_f64.powi(64) + *limb as f64)
+}
+
+// Rounds to the nearest integer, negative and non finite amounts become zero
+pub fn f64_to_u256(amount: f64) -> U256 {
+    match amount.is_finite() && amount >= 1.0 {
+        true => U256::from_dec_str(&format!("{:.0}", amount)).unwrap_or(U256::MAX),
+        false => U256::zero(),
+    }
+}
+
+// Swaps `amount_in` of `token_in` through `pools` in order, returns the amount after each hop
+// with `amount_in` first
+pub async fn simulate_path_amounts<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    amount_in: U256,
+    middleware: Arc<M>,
+) -> Result<Vec<U256>, ExecutorError<M>> {
+    let mut token = token_in;
+    let mut amounts = vec![amount_in];
+    for pool in pools {
+        let amount = *amounts.last().unwrap();
+        let amount_out = match amount.is_zero() {
+            true => U256::zero(),
+            false => pool.simulate_swap(token, amount, middleware.clone()).await?,
+        };
+        amounts.push(amount_out);
+        token = pool_token_out(pool, token);
+    }
+    Ok(amounts)
+}
+
+pub async fn simulate_path<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    amount_in: U256,
+    middleware: Arc<M>,
+) -> Result<U256, ExecutorError<M>> {
+    let amounts = simulate_path_amounts(pools, token_in, amount_in, middleware).await?;
+    Ok(*amounts.last().unwrap())
+}
+
+// Spot exchange rate of the pool in raw token units, token_out received per unit of token_in
+pub fn spot_rate(pool: &Pool, token_in: H160) -> Option<f64> {
+    if !pool_has_liquidity(pool) {
+        return None;
+    }
+
+    let price = pool.calculate_price(token_in).ok()?;
+    let (decimals_in, decimals_out) = pool_decimals(pool, token_in);
+    let rate = price * 10_f64.powi(decimals_out as i32 - decimals_in as i32);
+
+    match rate.is_finite() && rate > 0.0 {
+        true => Some(rate),
+        false => None,
+    }
+}
+
+// Spot rate token_in -> token_out of the pool holding the most token_in, the pools all trade the
+// same pair
+pub fn deepest_spot_rate<'a>(
+    pools: impl IntoIterator<Item = &'a Pool>,
+    token_in: H160,
+) -> Option<f64> {
+    pools
+        .into_iter()
+        .filter_map(|pool| spot_rate(pool, token_in).map(|rate| (pool_depth(pool, token_in), rate)))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, rate)| rate)
+}
+
+// Share of the input taken as fee, V2 fees are in 1e-5 units and V3 fees in 1e-6 units
+pub fn fee_fraction(pool: &Pool) -> f64 {
+    match pool {
+        Pool::UniswapV2(pool) => pool.fee as f64 / 100_000.0,
+        Pool::UniswapV3(pool) => pool.fee as f64 / 1_000_000.0,
+    }
+}
+
 pub async fn get_market_x<M: 'static + Middleware>(
     token_a: H160,
     token_b: H160,
@@ -111,10 +310,15 @@ pub fn get_best_market_price(
     best_price
 }
 
+// Syncs every pool of the dexes. With a checkpoint path the pools are resumed from the checkpoint
+// there, only pools created and state changed since its block are synced, and the checkpoint is
+// written again at the current block.
 pub async fn get_all_markets<M: 'static + Middleware>(
     dexes: Vec<Dex>,
+    requests_per_second_limit: usize,
+    checkpoint_path: Option<&str>,
     middleware: Arc<M>,
-) -> Result<Vec<Pool>, CFMMError<M>> {
+) -> Result<Vec<Pool>, ExecutorError<M>> {
     // let mut market = HashMap::new();
 
     // let pools = AllowStdIo::get_pools(token_a, token_b);
@@ -126,7 +330,21 @@ pub async fn get_all_markets<M: 'static + Middleware>(
                                 .await
                                 .map_err(CFMMError::MiddlewareError)?;
     //Initialize a new request throttle
-    let request_throttle = Arc::new(Mutex::new(RequestThrottle::new(10)));
+    let request_throttle = Arc::new(Mutex::new(RequestThrottle::new(requests_per_second_limit)));
+
+    // A missing or empty checkpoint file starts a full sync
+    let checkpoint = match checkpoint_path {
+        Some(path) if metadata(path).is_ok_and(|metadata| metadata.len() > 0) => {
+            let checkpoint = checkpoint::load_checkpoint(path)?;
+            tracing::debug!(
+                "Resuming {} pools from the checkpoint at block {}",
+                checkpoint.pools.len(),
+                checkpoint.block_number
+            );
+            Some(checkpoint)
+        }
+        _ => None,
+    };
 
     //Aggregate the populated pools from each thread
     let mut aggregated_pools: Vec<Pool> = vec![];
@@ -137,6 +355,11 @@ pub async fn get_all_markets<M: 'static + Middleware>(
 
     //For each dex supplied, get all pair created events and get reserve values
     for dex in dexes.clone() {
+        // Dexes already in the checkpoint only look for pools created after it
+        let from_block = checkpoint
+            .as_ref()
+            .filter(|checkpoint| checkpoint.factories.contains(&dex.factory_address()))
+            .map(|checkpoint| checkpoint.block_number + 1);
         let async_provider = middleware.clone();
         let request_throttle = request_throttle.clone();
         let progress_bar = multi_progress_bar.add(ProgressBar::new(0));
@@ -148,14 +371,29 @@ pub async fn get_all_markets<M: 'static + Middleware>(
                     .progress_chars("##-"),
             );
 
-            let mut pools = dex
-                .get_all_pools(
-                    request_throttle.clone(),
-                    100000000,
-                    progress_bar.clone(),
-                    async_provider.clone(),
-                )
-                .await?;
+            let mut pools = match from_block {
+                Some(from_block) if from_block > current_block.as_u64() => vec![],
+                Some(from_block) => {
+                    dex.get_all_pools_from_logs_within_range(
+                        BlockNumber::Number(from_block.into()),
+                        BlockNumber::Number(current_block),
+                        POOL_CREATED_LOG_STEP,
+                        request_throttle.clone(),
+                        progress_bar.clone(),
+                        async_provider.clone(),
+                    )
+                    .await?
+                }
+                None => {
+                    dex.get_all_pools(
+                        request_throttle.clone(),
+                        100000000,
+                        progress_bar.clone(),
+                        async_provider.clone(),
+                    )
+                    .await?
+                }
+            };
             // println!("this is the all pools of specific dex ==============> {:?}\n", pools);
 
             progress_bar.reset();
@@ -198,22 +436,139 @@ pub async fn get_all_markets<M: 'static + Middleware>(
             }
         }
     }
-    // let checkpoint_path = Some("");
-    // //Save a checkpoint if a path is provided
-    // // if checkpoint_path.is_some() {
-    //     let checkpoint_path = checkpoint_path.unwrap();
 
-    //     checkpoint::construct_checkpoint(
-    //         dexes,
-    //         &aggregated_pools,
-    //         current_block.as_u64(),
-    //         checkpoint_path,
-    //     );
-    // // }
+    let mut tick_store = TickStore::new();
+    if let Some(checkpoint) = checkpoint {
+        let (checkpoint_pools, checkpoint_tick_store) = update_checkpoint_pools(
+            checkpoint,
+            &dexes,
+            current_block.as_u64(),
+            request_throttle.clone(),
+            middleware.clone(),
+        )
+        .await?;
+        aggregated_pools.extend(checkpoint_pools);
+        tick_store = checkpoint_tick_store;
+    }
+
+    //Save a checkpoint if a path is provided
+    if let Some(checkpoint_path) = checkpoint_path {
+        checkpoint::write_checkpoint(
+            checkpoint_path,
+            &dexes,
+            &aggregated_pools,
+            &tick_store,
+            current_block.as_u64(),
+        )?;
+    }
 
     // //Return the populated aggregated pools vec
     println!("THIS IS THE END OF THE GETTING ALL MARKETS");
 
 
     Ok(aggregated_pools)    
-}
\ No newline at end of file
+}
+
+// Brings the checkpoint's pools up to `current_block`. Sync and Swap logs carry the whole state of
+// a pool and are applied in order, V3 pools whose liquidity moved through a Mint or Burn are
+// fetched again from their own dex. Their ticks moved too, the checkpoint's tick data is returned
+// for the other pools only.
+async fn update_checkpoint_pools<M: 'static + Middleware>(
+    checkpoint: checkpoint::CheckpointPools,
+    dexes: &[Dex],
+    current_block: u64,
+    request_throttle: Arc<Mutex<RequestThrottle>>,
+    middleware: Arc<M>,
+) -> Result<(Vec<Pool>, TickStore), ExecutorError<M>> {
+    let mut market_store = checkpoint.market_store();
+    let mut tick_store = checkpoint.tick_store;
+    let mut liquidity_changed = HashSet::new();
+
+    let topics = state_sync::pool_state_topics();
+    let addresses: Vec<H160> = market_store.pools().map(|pool| pool.address()).collect();
+
+    for addresses in addresses.chunks(STATE_LOG_ADDRESSES) {
+        let mut step = STATE_LOG_STEP;
+        let mut from_block = checkpoint.block_number + 1;
+        while from_block <= current_block {
+            let to_block = (from_block + step - 1).min(current_block);
+            request_throttle.lock().unwrap().increment_or_sleep(1);
+
+            let logs = match middleware
+                .get_logs(
+                    &Filter::new()
+                        .address(addresses.to_vec())
+                        .topic0(topics.clone())
+                        .from_block(from_block)
+                        .to_block(to_block),
+                )
+                .await
+            {
+                Ok(logs) => logs,
+                // Providers cap the blocks or logs of a request, retry on half the range
+                Err(_) if to_block > from_block => {
+                    step = (to_block - from_block).div_ceil(2);
+                    continue;
+                }
+                Err(err) => return Err(ExecutorError::MiddlewareError(err)),
+            };
+
+            for log in logs {
+                match state_sync::is_liquidity_log(&log) {
+                    true => {
+                        liquidity_changed.insert(log.address);
+                    }
+                    false => {
+                        state_sync::apply_log(&mut market_store, &log);
+                    }
+                }
+            }
+
+            from_block = to_block + 1;
+            // Grow back after the range that failed, one error shouldn't slow the whole sync
+            step = (step * 2).min(STATE_LOG_STEP);
+        }
+    }
+
+    // The checkpoint doesn't say which factory a pool is from, the pool does
+    let mut dex_pools: HashMap<H160, Vec<Pool>> = HashMap::new();
+    for address in liquidity_changed {
+        tick_store.remove(address);
+        let Some(pool) = market_store.get_pool(address).copied() else {
+            continue;
+        };
+        request_throttle.lock().unwrap().increment_or_sleep(1);
+        let factory = IUniswapV3Pool::new(address, middleware.clone())
+            .factory()
+            .call()
+            .await?;
+        dex_pools.entry(factory).or_default().push(pool);
+    }
+
+    for (factory, mut pools) in dex_pools {
+        let Some(dex) = dexes.iter().find(|dex| match dex {
+            Dex::UniswapV3(uniswap_v3_dex) => uniswap_v3_dex.factory_address == factory,
+            _ => false,
+        }) else {
+            tracing::debug!(
+                "No dex for factory {:?}, {} pools keep their checkpoint liquidity",
+                factory,
+                pools.len()
+            );
+            continue;
+        };
+
+        dex.get_all_pool_data(
+            &mut pools,
+            request_throttle.clone(),
+            ProgressBar::hidden(),
+            middleware.clone(),
+        )
+        .await?;
+        for pool in pools {
+            market_store.insert(pool);
+        }
+    }
+
+    Ok((market_store.pools().copied().collect(), tick_store))
+}
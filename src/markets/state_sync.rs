@@ -0,0 +1,277 @@
+use std::sync::Arc;
+
+use cfmms::pool::Pool;
+use ethers::{
+    abi::RawLog,
+    contract::EthEvent,
+    providers::{Middleware, Provider, Ws},
+    types::{Filter, Log, H160, H256},
+};
+use futures::{Stream, StreamExt};
+use tokio::sync::RwLock;
+
+use crate::{
+    abi::{BurnFilter, MintFilter, SwapFilter, SyncFilter},
+    error::ExecutorError,
+    markets::{
+        journal::{StateJournal, DEFAULT_REORG_DEPTH},
+        store::MarketStore,
+        uniswap_v3::TickStore,
+    },
+};
+
+// Logs handed to the store at once, a burst of logs is applied under a single write lock
+const MAX_LOGS_PER_UPDATE: usize = 1000;
+
+// Events that move pool state, V2 Sync carries the reserves and V3 Swap the price, tick and
+// liquidity after the swap. V3 Mint and Burn move the liquidity of a tick range.
+pub fn pool_state_topics() -> Vec<H256> {
+    vec![
+        SyncFilter::signature(),
+        SwapFilter::signature(),
+        MintFilter::signature(),
+        BurnFilter::signature(),
+    ]
+}
+
+pub fn is_liquidity_log(log: &Log) -> bool {
+    log.topics
+        .first()
+        .is_some_and(|topic| *topic == MintFilter::signature() || *topic == BurnFilter::signature())
+}
+
+pub fn pool_state_filter(pools: Vec<H160>) -> Filter {
+    Filter::new().address(pools).topic0(pool_state_topics())
+}
+
+// Applies a Sync, Swap, Mint or Burn log to its pool, returns the pool address when a tracked pool
+// changed. Mint and Burn only change the active liquidity when their range holds the current tick.
+pub fn apply_log(market_store: &mut MarketStore, log: &Log) -> Option<H160> {
+    let pool = *market_store.get_pool(log.address)?;
+    let raw_log = RawLog::from(log.clone());
+
+    let pool = match pool {
+        Pool::UniswapV2(mut uniswap_v2_pool) => {
+            let sync = SyncFilter::decode_log(&raw_log).ok()?;
+            uniswap_v2_pool.reserve_0 = sync.reserve_0;
+            uniswap_v2_pool.reserve_1 = sync.reserve_1;
+            Pool::UniswapV2(uniswap_v2_pool)
+        }
+        Pool::UniswapV3(mut uniswap_v3_pool) => {
+            let in_range = |tick_lower: i32, tick_upper: i32| {
+                tick_lower <= uniswap_v3_pool.tick && uniswap_v3_pool.tick < tick_upper
+            };
+
+            if let Ok(swap) = SwapFilter::decode_log(&raw_log) {
+                uniswap_v3_pool.sqrt_price = swap.sqrt_price_x96;
+                uniswap_v3_pool.liquidity = swap.liquidity;
+                uniswap_v3_pool.tick = swap.tick;
+            } else if let Ok(mint) = MintFilter::decode_log(&raw_log) {
+                if in_range(mint.tick_lower, mint.tick_upper) {
+                    uniswap_v3_pool.liquidity += mint.amount;
+                }
+            } else {
+                let burn = BurnFilter::decode_log(&raw_log).ok()?;
+                if in_range(burn.tick_lower, burn.tick_upper) {
+                    uniswap_v3_pool.liquidity =
+                        uniswap_v3_pool.liquidity.saturating_sub(burn.amount);
+                }
+            }
+            Pool::UniswapV3(uniswap_v3_pool)
+        }
+    };
+
+    market_store.insert(pool);
+    Some(log.address)
+}
+
+// Pools kept live by the state sync, with the journal that undoes their most recent blocks and
+// the tick data of the synced V3 pools
+#[derive(Debug, Clone, Default)]
+pub struct PoolState {
+    pub market_store: MarketStore,
+    pub journal: StateJournal,
+    pub tick_store: TickStore,
+}
+
+impl PoolState {
+    // `block_number` is the block the store was synced at
+    pub fn new(market_store: MarketStore, block_number: u64) -> PoolState {
+        PoolState {
+            market_store,
+            journal: StateJournal::new(block_number, DEFAULT_REORG_DEPTH),
+            tick_store: TickStore::new(),
+        }
+    }
+
+    // Newest block whose state can't be rolled back anymore, decisions made on the current state
+    // are final up to this block
+    pub fn last_safe_block(&self) -> u64 {
+        self.journal.last_safe_block()
+    }
+}
+
+// Applies logs in block and log order, rolling back first when a log shows the chain moved to
+// another branch. Tick data of pools whose liquidity moved is dropped to be synced again. Returns
+// the last block that changed a pool.
+pub fn apply_logs(pool_state: &mut PoolState, mut logs: Vec<Log>) -> Option<u64> {
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+
+    let PoolState {
+        market_store,
+        journal,
+        tick_store,
+    } = pool_state;
+
+    let mut last_block = None;
+    for log in &logs {
+        let (block_number, block_hash) = match (log.block_number, log.block_hash) {
+            (Some(block_number), Some(block_hash)) => (block_number.as_u64(), block_hash),
+            _ => continue,
+        };
+
+        // The node takes back the logs of blocks that left the canonical chain
+        if log.removed == Some(true) {
+            journal.rollback_block_hash(market_store, block_hash);
+            continue;
+        }
+        // Mint and Burn are deltas, a log delivered again must not be applied twice
+        if log
+            .log_index
+            .is_some_and(|log_index| journal.is_applied(block_hash, log_index))
+        {
+            continue;
+        }
+
+        // A block journaled under another hash, or a block below the head that was never seen,
+        // means the chain moved to another branch
+        let reorged = match journal.block_hash(block_number) {
+            Some(journaled_hash) => journaled_hash != block_hash,
+            None => journal
+                .head()
+                .is_some_and(|(head_number, _)| block_number < head_number),
+        };
+        if reorged {
+            journal.rollback(market_store, block_number);
+        }
+
+        let pool = match market_store.get_pool(log.address) {
+            Some(pool) => *pool,
+            None => continue,
+        };
+        if apply_log(market_store, log).is_some() {
+            journal.record(block_number, block_hash, log.log_index, pool);
+            last_block = Some(block_number);
+            if is_liquidity_log(log) {
+                tick_store.remove(log.address);
+            }
+        }
+    }
+    last_block
+}
+
+// Keeps the pools in step with a log stream until it ends. Logs that arrive together, which is
+// how a node delivers the logs of a block, are applied under one write lock so readers never see
+// a block half applied. Returns the last block that changed a pool.
+pub async fn sync_pool_state<S>(pool_state: Arc<RwLock<PoolState>>, logs: S) -> Option<u64>
+where
+    S: Stream<Item = Log> + Unpin,
+{
+    let mut last_block = None;
+    let mut updates = logs.ready_chunks(MAX_LOGS_PER_UPDATE);
+
+    while let Some(logs) = updates.next().await {
+        let mut pool_state = pool_state.write().await;
+        if let Some(block_number) = apply_logs(&mut pool_state, logs) {
+            last_block = Some(block_number);
+        }
+    }
+
+    last_block
+}
+
+// Subscribes to the state logs of every tracked pool and to new blocks over the WebSocket endpoint,
+// then catches up on the logs since the block the pools were synced at. When a new block's parent
+// isn't the journaled one the pools are rolled back and the logs of the new branch are fetched
+// again. Runs until either subscription ends.
+pub async fn run_state_sync(
+    ws_endpoint: &str,
+    pool_state: Arc<RwLock<PoolState>>,
+) -> Result<Option<u64>, ExecutorError<Provider<Ws>>> {
+    let provider = Provider::<Ws>::connect(ws_endpoint).await?;
+
+    let pools: Vec<H160> = pool_state
+        .read()
+        .await
+        .market_store
+        .pools()
+        .map(|pool| pool.address())
+        .collect();
+    // A filter without addresses would match the logs of every pool on the chain
+    if pools.is_empty() {
+        return Err(ExecutorError::NoTrackedPools);
+    }
+
+    let filter = pool_state_filter(pools);
+    let mut updates = provider
+        .subscribe_logs(&filter)
+        .await?
+        .ready_chunks(MAX_LOGS_PER_UPDATE);
+    let mut blocks = provider.subscribe_blocks().await?;
+
+    // Logs that also come through the subscription are skipped as already applied
+    let synced_block = pool_state.read().await.last_safe_block();
+    let logs = provider
+        .get_logs(&filter.clone().from_block(synced_block + 1))
+        .await?;
+    let mut last_block = apply_logs(&mut *pool_state.write().await, logs);
+    loop {
+        tokio::select! {
+            logs = updates.next() => {
+                let logs = match logs {
+                    Some(logs) => logs,
+                    None => break,
+                };
+                if let Some(block_number) = apply_logs(&mut *pool_state.write().await, logs) {
+                    last_block = Some(block_number);
+                }
+            }
+            block = blocks.next() => {
+                let block = match block {
+                    Some(block) => block,
+                    None => break,
+                };
+                let block_number = match block.number {
+                    Some(block_number) => block_number.as_u64(),
+                    None => continue,
+                };
+
+                let mut pool_state = pool_state.write().await;
+                let PoolState { market_store, journal, .. } = &mut *pool_state;
+                let fork_block =
+                    journal.check_parent(market_store, block_number, block.parent_hash);
+                if let Some(mut fork_block) = fork_block {
+                    // Walk back until the journaled parent matches the chain again
+                    while let Some(parent) = provider.get_block(fork_block).await? {
+                        match journal.check_parent(market_store, fork_block, parent.parent_hash) {
+                            Some(earlier_block) => fork_block = earlier_block,
+                            None => break,
+                        }
+                    }
+
+                    // Logs of the new branch that also come through the subscription are skipped
+                    // as already applied
+                    let logs = provider
+                        .get_logs(&filter.clone().from_block(fork_block).to_block(block_number))
+                        .await?;
+                    apply_logs(&mut pool_state, logs);
+                }
+
+                // Blocks become final with the chain head, not only when pools change
+                pool_state.journal.advance(block_number);
+            }
+        }
+    }
+
+    Ok(last_block)
+}
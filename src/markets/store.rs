@@ -0,0 +1,134 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use cfmms::{dex::Dex, pool::Pool};
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    error::ExecutorError,
+    markets::{get_market, get_market_id, pool_tokens, Market},
+};
+
+// Synced pools indexed by market id, by pool address and by token
+#[derive(Debug, Clone, Default)]
+pub struct MarketStore {
+    pools: HashMap<H160, Pool>,
+    markets: HashMap<U256, Market>,
+    // Ids of the markets each token trades in
+    token_markets: HashMap<H160, HashSet<U256>>,
+}
+
+impl MarketStore {
+    pub fn new() -> MarketStore {
+        MarketStore::default()
+    }
+
+    pub fn from_pools(pools: Vec<Pool>) -> MarketStore {
+        let mut market_store = MarketStore::new();
+        for pool in pools {
+            market_store.insert(pool);
+        }
+        market_store
+    }
+
+    // Adds the pool, or replaces the stored state of a pool with the same address
+    pub fn insert(&mut self, pool: Pool) {
+        let (token_a, token_b) = pool_tokens(&pool);
+        let market_id = get_market_id(token_a, token_b);
+
+        self.pools.insert(pool.address(), pool);
+        self.markets
+            .entry(market_id)
+            .or_default()
+            .insert(pool.address(), pool);
+        for token in [token_a, token_b] {
+            self.token_markets
+                .entry(token)
+                .or_default()
+                .insert(market_id);
+        }
+    }
+
+    pub fn remove(&mut self, address: H160) -> Option<Pool> {
+        let pool = self.pools.remove(&address)?;
+        let (token_a, token_b) = pool_tokens(&pool);
+        let market_id = get_market_id(token_a, token_b);
+
+        if let Some(market) = self.markets.get_mut(&market_id) {
+            market.remove(&address);
+            if market.is_empty() {
+                self.markets.remove(&market_id);
+                for token in [token_a, token_b] {
+                    if let Some(market_ids) = self.token_markets.get_mut(&token) {
+                        market_ids.remove(&market_id);
+                        if market_ids.is_empty() {
+                            self.token_markets.remove(&token);
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(pool)
+    }
+
+    pub fn get_pool(&self, address: H160) -> Option<&Pool> {
+        self.pools.get(&address)
+    }
+
+    pub fn get_market(&self, token_a: H160, token_b: H160) -> Option<&Market> {
+        self.markets.get(&get_market_id(token_a, token_b))
+    }
+
+    pub fn get_market_by_id(&self, market_id: U256) -> Option<&Market> {
+        self.markets.get(&market_id)
+    }
+
+    // Every pool that trades `token`
+    pub fn pools_for_token(&self, token: H160) -> impl Iterator<Item = &Pool> + '_ {
+        self.token_markets
+            .get(&token)
+            .into_iter()
+            .flatten()
+            .filter_map(|market_id| self.markets.get(market_id))
+            .flat_map(|market| market.values())
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &Pool> + '_ {
+        self.pools.values()
+    }
+
+    // The pair's market from the store, fetched from the dexes and kept when the pair is unknown
+    pub async fn get_or_fetch_market<M: 'static + Middleware>(
+        &mut self,
+        token_a: H160,
+        token_b: H160,
+        dexes: &[Dex],
+        middleware: Arc<M>,
+    ) -> Result<Option<Market>, ExecutorError<M>> {
+        if let Some(market) = self.get_market(token_a, token_b) {
+            return Ok(Some(market.clone()));
+        }
+
+        let market = get_market(token_a, token_b, dexes, middleware).await?;
+        if let Some(market) = &market {
+            for pool in market.values() {
+                self.insert(*pool);
+            }
+        }
+        Ok(market)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
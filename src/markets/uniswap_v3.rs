@@ -0,0 +1,320 @@
+use std::{collections::HashMap, sync::Arc};
+
+use cfmms::pool::{Pool, UniswapV3Pool};
+use ethers::{
+    providers::Middleware,
+    types::{H160, I256, U256},
+};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use uniswap_v3_math::{
+    error::UniswapV3MathError,
+    liquidity_math, swap_math,
+    tick_bitmap::next_initialized_tick_within_one_word,
+    tick_math::{self, MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK},
+};
+
+use crate::{abi::IUniswapV3Pool, error::ExecutorError};
+
+// eth_calls sent at once while syncing tick data
+const TICK_SYNC_BATCH_SIZE: usize = 100;
+// Bitmap words read on each side of the pool's current word, 256 tick spacings each
+const TICK_SYNC_WORDS: i32 = 16;
+
+// Initialized ticks of a V3 pool, enough to replay a swap without touching the chain
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TickData {
+    pub block_number: u64,
+    pub tick_spacing: i32,
+    pub tick_bitmap: HashMap<i16, U256>,
+    // Liquidity net of each initialized tick
+    pub ticks: HashMap<i32, i128>,
+    // First and last bitmap word read, None when the whole bitmap was. Swaps stop at its edges.
+    #[serde(default)]
+    pub word_range: Option<(i16, i16)>,
+}
+
+// Reads the pool's price, tick and liquidity, the bitmap words around its tick and the liquidity
+// net of every initialized tick in them, all at the same block. The pool is updated to that state.
+pub async fn sync_tick_data<M: 'static + Middleware>(
+    pool: &mut UniswapV3Pool,
+    middleware: Arc<M>,
+) -> Result<TickData, ExecutorError<M>> {
+    let contract = IUniswapV3Pool::new(pool.address, middleware.clone());
+    let block_number = middleware
+        .get_block_number()
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
+
+    let tick_spacing = match pool.tick_spacing {
+        0 => contract.tick_spacing().block(block_number).call().await?,
+        tick_spacing => tick_spacing,
+    };
+    let (sqrt_price, tick, ..) = contract.slot_0().block(block_number).call().await?;
+    let liquidity = contract.liquidity().block(block_number).call().await?;
+    pool.sqrt_price = sqrt_price;
+    pool.tick = tick;
+    pool.liquidity = liquidity;
+    pool.tick_spacing = tick_spacing;
+
+    let word = tick.div_euclid(tick_spacing) >> 8;
+    let min_word = ((MIN_TICK / tick_spacing - 1) >> 8).max(word - TICK_SYNC_WORDS);
+    let max_word = ((MAX_TICK / tick_spacing + 1) >> 8).min(word + TICK_SYNC_WORDS);
+    let word_positions: Vec<i16> = (min_word..=max_word).map(|word| word as i16).collect();
+
+    let mut tick_bitmap = HashMap::new();
+    for chunk in word_positions.chunks(TICK_SYNC_BATCH_SIZE) {
+        let calls = chunk
+            .iter()
+            .map(|word_position| contract.tick_bitmap(*word_position).block(block_number));
+        let words = join_all(calls.map(|call| async move { call.call().await })).await;
+
+        for (word_position, word) in chunk.iter().zip(words) {
+            let word = word?;
+            if !word.is_zero() {
+                tick_bitmap.insert(*word_position, word);
+            }
+        }
+    }
+
+    let initialized_ticks: Vec<i32> = tick_bitmap
+        .iter()
+        .flat_map(|(word_position, word)| {
+            (0..256)
+                .filter(|bit| word.bit(*bit))
+                .map(move |bit| ((*word_position as i32) * 256 + bit as i32) * tick_spacing)
+        })
+        .collect();
+
+    let mut ticks = HashMap::new();
+    for chunk in initialized_ticks.chunks(TICK_SYNC_BATCH_SIZE) {
+        let calls = chunk
+            .iter()
+            .map(|tick| contract.ticks(*tick).block(block_number));
+        let infos = join_all(calls.map(|call| async move { call.call().await })).await;
+
+        for (tick, info) in chunk.iter().zip(infos) {
+            ticks.insert(*tick, info?.1);
+        }
+    }
+
+    Ok(TickData {
+        block_number: block_number.as_u64(),
+        tick_spacing,
+        tick_bitmap,
+        ticks,
+        word_range: Some((min_word as i16, max_word as i16)),
+    })
+}
+
+// Exact input swap replayed from the pool state and its tick data, the same steps as
+// UniswapV3Pool.swap so the output matches the quoter
+pub fn simulate_swap(
+    pool: &UniswapV3Pool,
+    tick_data: &TickData,
+    token_in: H160,
+    amount_in: U256,
+) -> Result<U256, UniswapV3MathError> {
+    if amount_in.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    let (_, amount_out) = swap(
+        pool,
+        tick_data,
+        token_in == pool.token_a,
+        I256::from_raw(amount_in),
+    )?;
+    Ok(amount_out)
+}
+
+// Input needed to receive exactly `amount_out`, None when the pool runs out of liquidity first
+pub fn simulate_swap_exact_output(
+    pool: &UniswapV3Pool,
+    tick_data: &TickData,
+    token_in: H160,
+    amount_out: U256,
+) -> Result<Option<U256>, UniswapV3MathError> {
+    if amount_out.is_zero() {
+        return Ok(Some(U256::zero()));
+    }
+
+    let (amount_in, amount_received) = swap(
+        pool,
+        tick_data,
+        token_in == pool.token_a,
+        -I256::from_raw(amount_out),
+    )?;
+    match amount_received >= amount_out {
+        true => Ok(Some(amount_in)),
+        false => Ok(None),
+    }
+}
+
+// Positive `amount_specified` is an exact input and negative an exact output, as in the pool.
+// Returns the amount in including fees and the amount out.
+fn swap(
+    pool: &UniswapV3Pool,
+    tick_data: &TickData,
+    zero_for_one: bool,
+    amount_specified: I256,
+) -> Result<(U256, U256), UniswapV3MathError> {
+    let exact_input = amount_specified > I256::zero();
+    // Past the words that were read the ticks aren't known, the swap ends there as if out of
+    // liquidity
+    let sqrt_price_limit = match (zero_for_one, tick_data.word_range) {
+        (true, None) => MIN_SQRT_RATIO + 1,
+        (false, None) => MAX_SQRT_RATIO - 1,
+        (true, Some((min_word, _))) => {
+            let tick = (min_word as i32 * 256 * tick_data.tick_spacing).max(MIN_TICK);
+            tick_math::get_sqrt_ratio_at_tick(tick)?.max(MIN_SQRT_RATIO + 1)
+        }
+        (false, Some((_, max_word))) => {
+            let tick = ((max_word as i32 + 1) * 256 * tick_data.tick_spacing).min(MAX_TICK);
+            tick_math::get_sqrt_ratio_at_tick(tick)?.min(MAX_SQRT_RATIO - 1)
+        }
+    };
+
+    let mut sqrt_price = pool.sqrt_price;
+    let mut tick = pool.tick;
+    let mut liquidity = pool.liquidity;
+    let mut amount_remaining = amount_specified;
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+
+    while !amount_remaining.is_zero() && sqrt_price != sqrt_price_limit {
+        let sqrt_price_start = sqrt_price;
+
+        let (tick_next, initialized) = next_initialized_tick_within_one_word(
+            &tick_data.tick_bitmap,
+            tick,
+            tick_data.tick_spacing,
+            zero_for_one,
+        )?;
+        let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next = tick_math::get_sqrt_ratio_at_tick(tick_next)?;
+
+        let sqrt_price_target = match zero_for_one {
+            true => sqrt_price_next.max(sqrt_price_limit),
+            false => sqrt_price_next.min(sqrt_price_limit),
+        };
+
+        let (sqrt_price_after, step_amount_in, step_amount_out, step_fee) =
+            swap_math::compute_swap_step(
+                sqrt_price,
+                sqrt_price_target,
+                liquidity,
+                amount_remaining,
+                pool.fee,
+            )?;
+        sqrt_price = sqrt_price_after;
+
+        match exact_input {
+            true => amount_remaining -= I256::from_raw(step_amount_in + step_fee),
+            false => amount_remaining += I256::from_raw(step_amount_out),
+        }
+        amount_in += step_amount_in + step_fee;
+        amount_out += step_amount_out;
+
+        if sqrt_price == sqrt_price_next {
+            if initialized {
+                let liquidity_net = tick_data.ticks.get(&tick_next).copied().unwrap_or(0);
+                let liquidity_net = match zero_for_one {
+                    true => -liquidity_net,
+                    false => liquidity_net,
+                };
+                liquidity = liquidity_math::add_delta(liquidity, liquidity_net)?;
+            }
+
+            tick = match zero_for_one {
+                true => tick_next - 1,
+                false => tick_next,
+            };
+        } else if sqrt_price != sqrt_price_start {
+            tick = tick_math::get_tick_at_sqrt_ratio(sqrt_price)?;
+        }
+    }
+
+    Ok((amount_in, amount_out))
+}
+
+// Tick data of the synced V3 pools, keyed by pool address
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TickStore {
+    pools: HashMap<H160, TickData>,
+}
+
+impl TickStore {
+    pub fn new() -> TickStore {
+        TickStore::default()
+    }
+
+    // Syncs the tick data of every V3 pool in `pools` that isn't in the store yet. Returns those
+    // pools with their state read at the block of their tick data.
+    pub async fn sync_pools<M: 'static + Middleware>(
+        &mut self,
+        pools: &[Pool],
+        middleware: Arc<M>,
+    ) -> Result<Vec<Pool>, ExecutorError<M>> {
+        let mut synced = vec![];
+        for pool in pools {
+            if let Pool::UniswapV3(mut uniswap_v3_pool) = *pool {
+                if self.pools.contains_key(&uniswap_v3_pool.address) {
+                    continue;
+                }
+                let tick_data = sync_tick_data(&mut uniswap_v3_pool, middleware.clone()).await?;
+                self.pools.insert(uniswap_v3_pool.address, tick_data);
+                synced.push(Pool::UniswapV3(uniswap_v3_pool));
+            }
+        }
+        Ok(synced)
+    }
+
+    pub fn insert(&mut self, pool: H160, tick_data: TickData) {
+        self.pools.insert(pool, tick_data);
+    }
+
+    // Drops tick data that no longer matches the pool, the next sync fetches it again
+    pub fn remove(&mut self, pool: H160) -> Option<TickData> {
+        self.pools.remove(&pool)
+    }
+
+    // Tick data in `other` replaces tick data of the same pools
+    pub fn extend(&mut self, other: TickStore) {
+        self.pools.extend(other.pools);
+    }
+
+    pub fn get(&self, pool: H160) -> Option<&TickData> {
+        self.pools.get(&pool)
+    }
+
+    // None when the pool has no tick data in the store
+    pub fn simulate_swap(
+        &self,
+        pool: &UniswapV3Pool,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Option<Result<U256, UniswapV3MathError>> {
+        self.get(pool.address)
+            .map(|tick_data| simulate_swap(pool, tick_data, token_in, amount_in))
+    }
+
+    // None when the pool has no tick data in the store
+    pub fn simulate_swap_exact_output(
+        &self,
+        pool: &UniswapV3Pool,
+        token_in: H160,
+        amount_out: U256,
+    ) -> Option<Result<Option<U256>, UniswapV3MathError>> {
+        self.get(pool.address)
+            .map(|tick_data| simulate_swap_exact_output(pool, tick_data, token_in, amount_out))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+use futures::stream::{self, StreamExt};
+
+use crate::{
+    abi::IUniswapV3Quoter,
+    error::ExecutorError,
+    markets::{pool_token_out, v2_amount_in, Market},
+    routing::{
+        market_token_out,
+        route::{PoolDiagnostic, Route, SkipReason},
+        QuoteContext, MAX_CONCURRENT_SIMULATIONS,
+    },
+};
+
+// Amount of `token_in` needed to receive exactly `amount_out` from the pool, None when the pool
+// can't deliver it. V3 pools are quoted from their tick data, or by the quoter when not synced.
+pub async fn quote_exact_output<M: 'static + Middleware>(
+    pool: &Pool,
+    token_in: H160,
+    amount_out: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Option<U256>, ExecutorError<M>> {
+    match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => Ok(v2_amount_in(uniswap_v2_pool, token_in, amount_out)),
+        Pool::UniswapV3(uniswap_v3_pool) => {
+            if let Some(amount_in) =
+                context
+                    .tick_store
+                    .simulate_swap_exact_output(uniswap_v3_pool, token_in, amount_out)
+            {
+                return Ok(amount_in?);
+            }
+
+            let v3_quoter = context
+                .v3_quoter
+                .ok_or(ExecutorError::NoV3Quoter(uniswap_v3_pool.address))?;
+            let uniswap_v3_quoter = IUniswapV3Quoter::new(v3_quoter, middleware);
+            // The quoter reverts when the pool can't fill the output
+            Ok(uniswap_v3_quoter
+                .quote_exact_output_single(
+                    token_in,
+                    pool_token_out(pool, token_in),
+                    uniswap_v3_pool.fee,
+                    amount_out,
+                    U256::zero(),
+                )
+                .call()
+                .await
+                .ok())
+        }
+    }
+}
+
+// Route through `pools` ending with exactly `amount_out`, the amount needed before each hop is
+// worked backwards from the last pool. Each pool is paid enough to cover the transfer tax of its
+// token in, and the last one the tax of the token out on its way to the receiver.
+pub async fn quote_path_exact_output<M: 'static + Middleware>(
+    pools: &[Pool],
+    token_in: H160,
+    amount_out: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Option<Route>, ExecutorError<M>> {
+    let mut tokens = vec![token_in];
+    for pool in pools {
+        tokens.push(pool_token_out(pool, *tokens.last().unwrap()));
+    }
+
+    // What has to arrive at the receiver, then at each pool going backwards
+    let mut arriving = amount_out;
+    let mut amounts = vec![];
+    for (pool, hop_token_in) in pools.iter().zip(&tokens).rev() {
+        let hop_token_out = pool_token_out(pool, *hop_token_in);
+        let pool_amount_out =
+            match context.tokens.amount_before_transfer_tax(hop_token_out, arriving) {
+                Some(pool_amount_out) => pool_amount_out,
+                None => return Ok(None),
+            };
+
+        match quote_exact_output(
+            pool,
+            *hop_token_in,
+            pool_amount_out,
+            context,
+            middleware.clone(),
+        )
+        .await?
+        {
+            Some(pool_amount_in) => {
+                amounts.insert(0, pool_amount_out);
+                arriving = pool_amount_in;
+            }
+            None => return Ok(None),
+        }
+    }
+
+    match context.tokens.amount_before_transfer_tax(token_in, arriving) {
+        Some(amount_in) => {
+            amounts.insert(0, amount_in);
+            Ok(Some(Route::from_amounts(token_in, pools, &amounts)))
+        }
+        None => Ok(None),
+    }
+}
+
+// Route through the cheapest pool of each market delivering exactly `amount_out`, worked
+// backwards from the last market. Pools are ranked on their input plus gas priced in the hop's
+// token in, and paid enough to cover the transfer taxes like `quote_path_exact_output`.
+pub async fn find_best_route_across_markets_exact_output<M: 'static + Middleware>(
+    amount_out: U256,
+    token_in: H160,
+    markets: Vec<&Market>,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Route, ExecutorError<M>> {
+    let mut tokens = vec![token_in];
+    for market in &markets {
+        tokens.push(market_token_out(market, *tokens.last().unwrap()));
+    }
+    let token_out = *tokens.last().unwrap();
+
+    let mut arriving = amount_out;
+    let mut amounts = vec![];
+    let mut pools = vec![];
+    let mut diagnostics = vec![];
+    for (market, hop_token_in) in markets.iter().zip(&tokens).rev() {
+        let hop_token_out = market_token_out(market, *hop_token_in);
+        let Some(pool_amount_out) = context
+            .tokens
+            .amount_before_transfer_tax(hop_token_out, arriving)
+        else {
+            return Err(ExecutorError::NoViableRoute(token_in, token_out, diagnostics));
+        };
+
+        let (best, market_diagnostics) = best_quoted_pool(
+            market,
+            *hop_token_in,
+            pool_amount_out,
+            context,
+            middleware.clone(),
+        )
+        .await;
+        diagnostics.extend(market_diagnostics);
+
+        let Some((pool, pool_amount_in)) = best else {
+            return Err(ExecutorError::NoViableRoute(token_in, token_out, diagnostics));
+        };
+        amounts.insert(0, pool_amount_out);
+        pools.insert(0, pool);
+        arriving = pool_amount_in;
+    }
+
+    let amount_in = match context.tokens.amount_before_transfer_tax(token_in, arriving) {
+        Some(amount_in) if !pools.is_empty() => amount_in,
+        _ => return Err(ExecutorError::NoViableRoute(token_in, token_out, diagnostics)),
+    };
+    amounts.insert(0, amount_in);
+
+    Ok(Route::from_amounts(token_in, &pools, &amounts).with_diagnostics(diagnostics))
+}
+
+// Route through the pool of the market that delivers `amount_out` for the least `token_in`,
+// gas included
+pub async fn find_best_a_to_b_route_exact_output<M: 'static + Middleware>(
+    market: Market,
+    token_in: H160,
+    amount_out: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Route, ExecutorError<M>> {
+    find_best_route_across_markets_exact_output(
+        amount_out,
+        token_in,
+        vec![&market],
+        context,
+        middleware,
+    )
+    .await
+}
+
+// Quotes every pool of the market at most MAX_CONCURRENT_SIMULATIONS at a time, returning the
+// pool needing the least input after gas along with what happened to every pool
+async fn best_quoted_pool<M: 'static + Middleware>(
+    market: &Market,
+    token_in: H160,
+    amount_out: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> (Option<(Pool, U256)>, Vec<PoolDiagnostic>) {
+    let quotes = stream::iter(market.values().copied())
+        .map(|pool| {
+            let middleware = middleware.clone();
+            async move {
+                let amount_in =
+                    quote_exact_output(&pool, token_in, amount_out, context, middleware).await;
+                (pool, amount_in)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_SIMULATIONS)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut best: Option<(Pool, U256, U256)> = None;
+    let mut diagnostics = vec![];
+    for (pool, amount_in) in quotes {
+        let amount_in = match amount_in {
+            Ok(amount_in) => amount_in,
+            Err(error) => {
+                diagnostics.push(PoolDiagnostic::failed(&pool, token_in, &error));
+                continue;
+            }
+        };
+
+        diagnostics.push(PoolDiagnostic::quoted(&pool, token_in, amount_in, amount_out));
+        let Some(amount_in) = amount_in else {
+            continue;
+        };
+
+        let total_cost = amount_in + context.gas_model.gas_cost(token_in, &[pool]);
+        if best.is_none_or(|(_, _, best_cost)| total_cost < best_cost) {
+            best = Some((pool, amount_in, total_cost));
+        }
+    }
+
+    if let Some((best_pool, _, _)) = best {
+        for diagnostic in diagnostics.iter_mut() {
+            if diagnostic.skipped.is_none() && diagnostic.pool != best_pool.address() {
+                diagnostic.skipped = Some(SkipReason::Outranked);
+            }
+        }
+    }
+
+    (best.map(|(pool, amount_in, _)| (pool, amount_in)), diagnostics)
+}
@@ -0,0 +1,177 @@
+use std::{collections::HashMap, sync::Arc};
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    config::Config,
+    constants::{UNISWAP_V2_SWAP_GAS, UNISWAP_V3_SWAP_GAS},
+    error::ExecutorError,
+    markets::{deepest_spot_rate, f64_to_u256, store::MarketStore, u256_to_f64},
+};
+
+pub fn pool_gas(pool: &Pool) -> u64 {
+    match pool {
+        Pool::UniswapV2(_) => UNISWAP_V2_SWAP_GAS,
+        Pool::UniswapV3(_) => UNISWAP_V3_SWAP_GAS,
+    }
+}
+
+pub fn route_gas(pools: &[Pool]) -> u64 {
+    pools.iter().map(pool_gas).sum()
+}
+
+// Gas price and the exchange rate from wei into the tokens routes pass through,
+// used to rank routes on their output after gas
+#[derive(Debug, Clone, Default)]
+pub struct GasModel {
+    pub gas_price: U256,
+    // Raw units of each token worth one wei
+    rates: HashMap<H160, f64>,
+}
+
+impl GasModel {
+    pub fn new(gas_price: U256, wrapped_native: H160) -> GasModel {
+        GasModel {
+            gas_price,
+            rates: HashMap::from([(wrapped_native, 1.0)]),
+        }
+    }
+
+    // Gas price from the config or the endpoint, with every token in `tokens` priced in wei
+    pub async fn from_config<M: 'static + Middleware>(
+        configuration: &Config,
+        tokens: &[H160],
+        market_store: &mut MarketStore,
+        middleware: Arc<M>,
+    ) -> Result<GasModel, ExecutorError<M>> {
+        let gas_price = match configuration.gas_price {
+            Some(gas_price) => gas_price,
+            None => middleware
+                .get_gas_price()
+                .await
+                .map_err(ExecutorError::MiddlewareError)?,
+        };
+
+        let mut gas_model = GasModel::new(gas_price, configuration.weth_address);
+        for token in tokens {
+            gas_model
+                .price_token(*token, configuration, market_store, middleware.clone())
+                .await?;
+        }
+
+        Ok(gas_model)
+    }
+
+    pub fn with_rate(mut self, token: H160, rate: f64) -> GasModel {
+        self.rates.insert(token, rate);
+        self
+    }
+
+    // Prices `token` through the deepest wrapped native market, or through an intermediate
+    // token when there is no direct market. Tokens without a price path are left unpriced.
+    pub async fn price_token<M: 'static + Middleware>(
+        &mut self,
+        token: H160,
+        configuration: &Config,
+        market_store: &mut MarketStore,
+        middleware: Arc<M>,
+    ) -> Result<(), ExecutorError<M>> {
+        let token = configuration.tokens.resolve(token);
+        if self.rates.contains_key(&token) {
+            return Ok(());
+        }
+
+        let weth = configuration.weth_address;
+        let mut rate = deepest_rate(weth, token, configuration, market_store, middleware.clone()).await?;
+
+        if rate.is_none() {
+            for connector in &configuration.intermediate_tokens {
+                if *connector == weth || *connector == token {
+                    continue;
+                }
+                let first =
+                    deepest_rate(weth, *connector, configuration, market_store, middleware.clone())
+                        .await?;
+                let second =
+                    deepest_rate(*connector, token, configuration, market_store, middleware.clone())
+                        .await?;
+                if let (Some(first), Some(second)) = (first, second) {
+                    rate = Some(first * second);
+                    break;
+                }
+            }
+        }
+
+        if let Some(rate) = rate {
+            self.rates.insert(token, rate);
+        }
+        Ok(())
+    }
+
+    pub fn rate(&self, token: H160) -> Option<f64> {
+        self.rates.get(&token).copied()
+    }
+
+    // Gas cost of swapping through `pools` in raw units of `token`, zero when the token is unpriced
+    pub fn gas_cost(&self, token: H160, pools: &[Pool]) -> U256 {
+        match self.rate(token) {
+            Some(rate) => f64_to_u256(route_gas(pools) as f64 * u256_to_f64(self.gas_price) * rate),
+            None => U256::zero(),
+        }
+    }
+
+    // Gas cost of swapping through `pools` in raw units of the last of `tokens`, where `amounts`
+    // holds the amount of each token along the route. The gas is priced in the last token with a
+    // rate and carried to the end at the route's own exchange rate, so hops ending in an unpriced
+    // token still pay for their gas. Zero when no token of the route is priced.
+    pub fn route_gas_cost(&self, tokens: &[H160], amounts: &[U256], pools: &[Pool]) -> U256 {
+        let amount_out = match amounts.last() {
+            Some(amount_out) => u256_to_f64(*amount_out),
+            None => return U256::zero(),
+        };
+
+        for (token, amount) in tokens.iter().zip(amounts).rev() {
+            if let Some(rate) = self.rate(*token) {
+                if amount.is_zero() {
+                    break;
+                }
+                let gas_cost = route_gas(pools) as f64 * u256_to_f64(self.gas_price) * rate;
+                return f64_to_u256(gas_cost * amount_out / u256_to_f64(*amount));
+            }
+        }
+
+        U256::zero()
+    }
+
+    pub fn net_amount_out(&self, tokens: &[H160], amounts: &[U256], pools: &[Pool]) -> U256 {
+        amounts
+            .last()
+            .copied()
+            .unwrap_or_default()
+            .saturating_sub(self.route_gas_cost(tokens, amounts, pools))
+    }
+}
+
+// Spot rate token_in -> token_out of the pair's pool with the most token_in liquidity, from the
+// store when it holds the pair
+async fn deepest_rate<M: 'static + Middleware>(
+    token_in: H160,
+    token_out: H160,
+    configuration: &Config,
+    market_store: &mut MarketStore,
+    middleware: Arc<M>,
+) -> Result<Option<f64>, ExecutorError<M>> {
+    let market = match market_store
+        .get_or_fetch_market(token_in, token_out, &configuration.dexes, middleware)
+        .await?
+    {
+        Some(market) => market,
+        None => return Ok(None),
+    };
+
+    Ok(deepest_spot_rate(market.values(), token_in))
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    error::ExecutorError,
+    markets::{pool_has_liquidity, pool_tokens, spot_rate, u256_to_f64, uniswap_v3::TickStore},
+    routing::{gas::GasModel, route::Route, simulate_pool, QuoteContext, RouteOptions},
+};
+
+// A pool seen from one of its tokens
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    pub token_out: H160,
+    pub pool: Pool,
+}
+
+// Tokens as nodes and pools as edges, one edge per swap direction
+#[derive(Debug, Clone, Default)]
+pub struct TokenGraph {
+    edges: HashMap<H160, Vec<Edge>>,
+    pool_count: usize,
+}
+
+impl TokenGraph {
+    pub fn new(pools: &[Pool]) -> TokenGraph {
+        let mut graph = TokenGraph::default();
+        for pool in pools {
+            graph.add_pool(*pool);
+        }
+        graph
+    }
+
+    // Pools without liquidity are left out since they can't quote anything
+    pub fn add_pool(&mut self, pool: Pool) {
+        if !pool_has_liquidity(&pool) {
+            return;
+        }
+
+        let (token_a, token_b) = pool_tokens(&pool);
+        self.edges.entry(token_a).or_default().push(Edge {
+            token_out: token_b,
+            pool,
+        });
+        self.edges.entry(token_b).or_default().push(Edge {
+            token_out: token_a,
+            pool,
+        });
+        self.pool_count += 1;
+    }
+
+    // Graph over the pools that simulate without the chain, V2 pools and V3 pools with tick data
+    pub fn local<'a>(
+        pools: impl IntoIterator<Item = &'a Pool>,
+        tick_store: &TickStore,
+    ) -> TokenGraph {
+        let mut graph = TokenGraph::default();
+        for pool in pools {
+            let local = match pool {
+                Pool::UniswapV3(uniswap_v3_pool) => tick_store.get(uniswap_v3_pool.address).is_some(),
+                Pool::UniswapV2(_) => true,
+            };
+            if local {
+                graph.add_pool(*pool);
+            }
+        }
+        graph
+    }
+
+    pub fn edges(&self, token: H160) -> &[Edge] {
+        self.edges
+            .get(&token)
+            .map(|edges| edges.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = H160> + '_ {
+        self.edges.keys().copied()
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn pool_count(&self) -> usize {
+        self.pool_count
+    }
+}
+
+// Tokens visited, pools taken and the amount after each hop, `amounts[0]` is the amount in
+#[derive(Debug, Clone)]
+struct GraphRoute {
+    tokens: Vec<H160>,
+    pools: Vec<Pool>,
+    amounts: Vec<U256>,
+    // Share of the spot value kept after fees and price impact, used to rank partial routes
+    efficiency: f64,
+}
+
+impl GraphRoute {
+    fn new(token_in: H160, amount_in: U256) -> GraphRoute {
+        GraphRoute {
+            tokens: vec![token_in],
+            pools: vec![],
+            amounts: vec![amount_in],
+            efficiency: 1.0,
+        }
+    }
+
+    fn extend(&self, edge: &Edge, amount_out: U256) -> GraphRoute {
+        let token_in = *self.tokens.last().unwrap();
+        let amount_in = *self.amounts.last().unwrap();
+        let spot_amount_out =
+            u256_to_f64(amount_in) * spot_rate(&edge.pool, token_in).unwrap_or(0.0);
+        let efficiency = if spot_amount_out > 0.0 {
+            u256_to_f64(amount_out) / spot_amount_out
+        } else {
+            0.0
+        };
+
+        let mut route = self.clone();
+        route.tokens.push(edge.token_out);
+        route.pools.push(edge.pool);
+        route.amounts.push(amount_out);
+        route.efficiency *= efficiency;
+        route
+    }
+
+    fn amount_out(&self) -> U256 {
+        *self.amounts.last().unwrap()
+    }
+
+    fn net_amount_out(&self, gas_model: &GasModel) -> U256 {
+        gas_model.net_amount_out(&self.tokens, &self.amounts, &self.pools)
+    }
+}
+
+// Beam search over the graph: at every hop only the best partial routes into each token are kept,
+// and only the `beam_width` most efficient of those are expanded further.
+pub async fn find_best_graph_route<M: 'static + Middleware>(
+    graph: &TokenGraph,
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    route_options: &RouteOptions,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Option<Route>, ExecutorError<M>> {
+    let routes = find_top_graph_routes(
+        graph,
+        token_in,
+        token_out,
+        amount_in,
+        1,
+        route_options,
+        context,
+        middleware,
+    )
+    .await?;
+
+    Ok(routes.into_iter().next())
+}
+
+// The `k` best distinct routes found by the beam search, best first. The `k` best partial routes
+// into each token are kept so alternatives through the same token survive, and every complete
+// route reached during the search competes on its output after gas. Synced V3 pools are simulated
+// from the tick store.
+#[allow(clippy::too_many_arguments)]
+pub async fn find_top_graph_routes<M: 'static + Middleware>(
+    graph: &TokenGraph,
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    k: usize,
+    route_options: &RouteOptions,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Vec<Route>, ExecutorError<M>> {
+    let mut complete_routes: Vec<(U256, GraphRoute)> = vec![];
+    let mut frontier = vec![GraphRoute::new(token_in, amount_in)];
+
+    for hop in 0..route_options.max_hops {
+        let last_hop = hop + 1 == route_options.max_hops;
+        let mut best_into_token: HashMap<H160, Vec<GraphRoute>> = HashMap::new();
+
+        for route in &frontier {
+            let token = *route.tokens.last().unwrap();
+
+            for edge in graph.edges(token) {
+                if route.tokens.contains(&edge.token_out)
+                    || route_options.is_skipped(token, edge.token_out)
+                    || (last_hop && edge.token_out != token_out)
+                {
+                    continue;
+                }
+
+                // The pool receives the amount after the transfer tax of the token
+                let pool_amount_in = context.tokens.apply_transfer_tax(token, route.amount_out());
+                let amount_out = match simulate_pool(
+                    &edge.pool,
+                    token,
+                    pool_amount_in,
+                    context.tick_store,
+                    middleware.clone(),
+                )
+                .await
+                {
+                    Ok(amount_out) if !amount_out.is_zero() => amount_out,
+                    _ => continue,
+                };
+
+                let next_route = route.extend(edge, amount_out);
+                if edge.token_out == token_out {
+                    complete_routes.push((next_route.net_amount_out(context.gas_model), next_route));
+                } else {
+                    best_into_token
+                        .entry(edge.token_out)
+                        .or_default()
+                        .push(next_route);
+                }
+            }
+        }
+
+        frontier = best_into_token
+            .into_values()
+            .flat_map(|mut routes| {
+                routes.sort_by_key(|route| std::cmp::Reverse(route.amount_out()));
+                routes.truncate(k.max(1));
+                routes
+            })
+            .collect();
+        frontier.sort_by(|a, b| b.efficiency.total_cmp(&a.efficiency));
+        frontier.truncate(route_options.beam_width);
+
+        if frontier.is_empty() {
+            break;
+        }
+    }
+
+    complete_routes.sort_by_key(|(net_amount_out, _)| std::cmp::Reverse(*net_amount_out));
+
+    let mut seen: HashSet<Vec<H160>> = HashSet::new();
+    Ok(complete_routes
+        .into_iter()
+        .filter(|(_, route)| seen.insert(route.pools.iter().map(|pool| pool.address()).collect()))
+        .take(k)
+        .map(|(_, route)| Route::from_amounts(token_in, &route.pools, &route.amounts))
+        .collect())
+}
@@ -1,26 +1,100 @@
-use cfmms::pool::{Pool, UniswapV2Pool};
-use ethers::providers::Middleware;
-use ethers::types::{H160, U256};
-use std::collections::HashMap;
-use std::hash::RandomState;
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashMap, hash::RandomState, sync::Arc};
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+use futures::stream::{self, StreamExt};
+
 use crate::{
     config::Config,
-    constants::WETH,
-    markets::{self, Market},
-    abi::IUniswapV3Quoter,
     error::ExecutorError,
+    markets::{self, store::MarketStore, uniswap_v3::TickStore, Market},
+    routing::{
+        gas::GasModel,
+        route::{PoolDiagnostic, Route, SkipReason},
+    },
+    tokens::TokenRegistry,
 };
-use futures::future::join_all;
 
-pub const V3_QUOTER_ADDRESS: H160 = H160([
-    178, 115, 8, 249, 249, 13, 96, 116, 99, 187, 51, 234, 27, 235, 180, 28, 39, 206, 90, 182,
-]);
+pub mod exact_output;
+pub mod gas;
+pub mod graph;
+pub mod route;
+pub mod split;
+
+// Pools of a market simulated at once
+pub const MAX_CONCURRENT_SIMULATIONS: usize = 16;
+
+// What pools are quoted against besides the amounts
+#[derive(Debug, Clone, Copy)]
+pub struct QuoteContext<'a> {
+    pub tokens: &'a TokenRegistry,
+    pub tick_store: &'a TickStore,
+    pub gas_model: &'a GasModel,
+    // Quotes V3 pools without tick data, None on chains without a quoter
+    pub v3_quoter: Option<H160>,
+}
+
+// Tokens that routes may hop through and pairs that should never be swapped directly
+#[derive(Debug, Clone)]
+pub struct RouteOptions {
+    pub connectors: Vec<H160>,
+    pub skip_pairs: Vec<(H160, H160)>,
+    pub max_hops: usize,
+    // Partial routes kept per hop by the graph router
+    pub beam_width: usize,
+    // Pieces an order is cut into by the split router
+    pub split_chunks: usize,
+    // Price impact of the best single route above which the order is split
+    pub split_price_impact: f64,
+}
+
+impl RouteOptions {
+    // Routes run between resolved tokens, so a native token in the config stands for its wrapped token
+    pub fn from_config(configuration: &Config) -> RouteOptions {
+        let tokens = &configuration.tokens;
+        RouteOptions {
+            connectors: configuration
+                .intermediate_tokens
+                .iter()
+                .map(|token| tokens.resolve(*token))
+                .collect(),
+            skip_pairs: configuration
+                .skip_pairs
+                .iter()
+                .map(|(token_a, token_b)| (tokens.resolve(*token_a), tokens.resolve(*token_b)))
+                .collect(),
+            max_hops: configuration.max_hops,
+            beam_width: configuration.beam_width,
+            split_chunks: configuration.split_chunks,
+            split_price_impact: configuration.split_price_impact,
+        }
+    }
+
+    pub fn with_connector(mut self, token: H160) -> RouteOptions {
+        if !token.is_zero() && !self.connectors.contains(&token) {
+            self.connectors.push(token);
+        }
+        self
+    }
+
+    pub fn is_skipped(&self, token_a: H160, token_b: H160) -> bool {
+        self.skip_pairs
+            .iter()
+            .any(|&(a, b)| (a == token_a && b == token_b) || (a == token_b && b == token_a))
+    }
+}
 
 fn merge_option_hashmaps<K, V>(
-    map1: Option<HashMap<K, V>>, 
-    map2: Option<HashMap<K, V>>
-) -> Option<HashMap<K, V>> where K: std::hash::Hash + Eq, V: Clone, {
+    map1: Option<HashMap<K, V>>,
+    map2: Option<HashMap<K, V>>,
+) -> Option<HashMap<K, V>>
+where
+    K: std::hash::Hash + Eq,
+    V: Clone,
+{
     match (map1, map2) {
         (None, None) => None,
         (Some(map), None) | (None, Some(map)) => Some(map),
@@ -35,22 +109,18 @@ pub async fn find_a_to_b_markets_and_route<M: 'static + Middleware>(
     token_in: H160,
     token_out: H160,
     configuration: &Config,
+    market_store: &mut MarketStore,
     middleware: Arc<M>,
 ) -> Result<HashMap<H160, Pool>, ExecutorError<M>> {
-    let markets = markets::get_market(
-        match token_in.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_in,
-        },
-        match token_out.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_out,
-        },
-        &configuration.dexes,
-        middleware,
-    )
-    .await?;
-    
+    let markets = market_store
+        .get_or_fetch_market(
+            configuration.tokens.resolve(token_in),
+            configuration.tokens.resolve(token_out),
+            &configuration.dexes,
+            middleware,
+        )
+        .await?;
+
     match markets {
         Some(markets) => {
             println!("Found markets in A-B: {:?}", markets.keys());
@@ -65,56 +135,264 @@ pub async fn find_a_to_b_markets_and_route<M: 'static + Middleware>(
     }
 }
 
+// Market of every hop of a path of resolved tokens, None when a hop has no market
+pub async fn find_path_markets<M: 'static + Middleware>(
+    path: &[H160],
+    configuration: &Config,
+    market_store: &mut MarketStore,
+    middleware: Arc<M>,
+) -> Result<Option<Vec<Market>>, ExecutorError<M>> {
+    let mut markets = vec![];
+    for pair in path.windows(2) {
+        match market_store
+            .get_or_fetch_market(pair[0], pair[1], &configuration.dexes, middleware.clone())
+            .await?
+        {
+            Some(market) => markets.push(market),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(markets))
+}
+
+// Syncs the tick data of the V3 pools on `paths` that aren't in the tick store yet, so routing
+// over them replays swaps locally instead of simulating every amount on the chain. Their state in
+// the market store is replaced by the one read along with the ticks.
+pub async fn sync_path_tick_data<M: 'static + Middleware>(
+    paths: &[Vec<H160>],
+    configuration: &Config,
+    market_store: &mut MarketStore,
+    tick_store: &mut TickStore,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    for path in paths {
+        let Some(markets) =
+            find_path_markets(path, configuration, market_store, middleware.clone()).await?
+        else {
+            continue;
+        };
+
+        let pools: Vec<Pool> = markets
+            .iter()
+            .flat_map(|market| market.values().copied())
+            .collect();
+        for pool in tick_store.sync_pools(&pools, middleware.clone()).await? {
+            market_store.insert(pool);
+        }
+    }
+
+    Ok(())
+}
+
+// Best route over token paths that all start with the same token, ranked on output after gas.
+// Paths missing a market for some hop are passed over, and when no path produces output the
+// error holds the diagnostics of every path.
+pub async fn find_best_path_route<M: 'static + Middleware>(
+    paths: &[Vec<H160>],
+    amount_in: U256,
+    configuration: &Config,
+    market_store: &mut MarketStore,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Route, ExecutorError<M>> {
+    let token_in = paths.first().map(|path| path[0]).unwrap_or_default();
+    let token_out = paths
+        .first()
+        .map(|path| *path.last().unwrap())
+        .unwrap_or_default();
+
+    let mut best: Option<(Route, U256)> = None;
+    let mut diagnostics = vec![];
+    for path in paths {
+        let markets =
+            match find_path_markets(path, configuration, market_store, middleware.clone()).await? {
+                Some(markets) => markets,
+                None => continue,
+            };
+
+        let route = match find_best_route_across_markets(
+            amount_in,
+            token_in,
+            markets.iter().collect(),
+            context,
+            middleware.clone(),
+        )
+        .await
+        {
+            Ok(route) => route,
+            Err(ExecutorError::NoViableRoute(_, _, path_diagnostics)) => {
+                diagnostics.extend(path_diagnostics);
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        let net_amount_out =
+            context
+                .gas_model
+                .net_amount_out(&route.tokens(), &route.amounts(), &route.pools());
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_net_amount_out)| net_amount_out > *best_net_amount_out)
+        {
+            best = Some((route, net_amount_out));
+        }
+    }
+
+    match best {
+        Some((route, _)) => Ok(route),
+        None => Err(ExecutorError::NoViableRoute(
+            token_in,
+            token_out,
+            diagnostics,
+        )),
+    }
+}
+
 pub async fn find_best_a_to_b_route<M: 'static + Middleware>(
     markets: HashMap<H160, Pool>,
     token_in: H160,
     amount: U256,
+    context: &QuoteContext<'_>,
     middleware: Arc<M>,
-) -> Result<(Pool, U256), ExecutorError<M>> {
-    let mut best_amount_out = U256::zero();
-    let mut best_pool = Pool::UniswapV2(UniswapV2Pool::default());
-    
-    for pool in markets.values() {
-        let pool: Pool = *pool;
-        match pool {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                let (token_in, token_out) = if token_in == uniswap_v2_pool.token_a {
-                    (uniswap_v2_pool.token_a, uniswap_v2_pool.token_b)
-                } else {
-                    (uniswap_v2_pool.token_b, uniswap_v2_pool.token_a)
-                };
-                let swap_amount_out = pool
-                    .simulate_swap(token_in, amount, middleware.clone())
-                    .await?;
-                
-                if swap_amount_out > best_amount_out {
-                    best_amount_out = swap_amount_out;
-                    best_pool = pool;
-                }
+) -> Result<Route, ExecutorError<M>> {
+    find_best_route_across_markets(amount, token_in, vec![&markets], context, middleware).await
+}
+
+// Simulates `amount_in` through every pool of the market, at most MAX_CONCURRENT_SIMULATIONS
+// at a time. V3 pools quote from their synced tick data, pools without it fall back to the chain.
+async fn simulate_market<M: 'static + Middleware>(
+    market: &Market,
+    token_in: H160,
+    amount_in: U256,
+    tick_store: &TickStore,
+    middleware: Arc<M>,
+) -> Vec<(Pool, Result<U256, ExecutorError<M>>)> {
+    stream::iter(market.values().copied())
+        .map(|pool| {
+            let middleware = middleware.clone();
+            async move {
+                let swap_amount_out =
+                    simulate_pool(&pool, token_in, amount_in, tick_store, middleware).await;
+                (pool, swap_amount_out)
             }
+        })
+        .buffer_unordered(MAX_CONCURRENT_SIMULATIONS)
+        .collect()
+        .await
+}
 
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                println!("this is the before the simulate swap. ===================> {:?}\n", uniswap_v3_pool);
-                let (token_in, token_out) = if token_in == uniswap_v3_pool.token_a {
-                    (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b)
-                } else {
-                    (uniswap_v3_pool.token_b, uniswap_v3_pool.token_a)
-                };
-                let swap_amount_out = pool
-                    .simulate_swap(token_in, amount, middleware.clone())
-                    .await?;
-                
-                if swap_amount_out > best_amount_out {
-                    best_amount_out = swap_amount_out;
-                    best_pool = pool;
-                }
+// Output of one pool, from its reserves and fee for V2 pools, from its tick data when it's a synced
+// V3 pool and from the chain otherwise
+pub async fn simulate_pool<M: 'static + Middleware>(
+    pool: &Pool,
+    token_in: H160,
+    amount_in: U256,
+    tick_store: &TickStore,
+    middleware: Arc<M>,
+) -> Result<U256, ExecutorError<M>> {
+    match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => {
+            return Ok(markets::v2_amount_out(uniswap_v2_pool, token_in, amount_in))
+        }
+        Pool::UniswapV3(uniswap_v3_pool) => {
+            if let Some(amount_out) = tick_store.simulate_swap(uniswap_v3_pool, token_in, amount_in)
+            {
+                return Ok(amount_out?);
+            }
+        }
+    }
 
-                println!("this is the after the simulate swap. ================> {:?}, {:?}, {:?}, {:?}, {:?}\n", swap_amount_out, uniswap_v3_pool.token_a, uniswap_v3_pool.token_b, uniswap_v3_pool, amount);
+    Ok(pool.simulate_swap(token_in, amount_in, middleware).await?)
+}
+
+// Amount after each pool of the path, each pool receiving the amount after the transfer tax of its
+// token in as in the routers
+pub async fn simulate_path_amounts<M: 'static + Middleware>(
+    path: &[Pool],
+    token_in: H160,
+    amount_in: U256,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<Vec<U256>, ExecutorError<M>> {
+    let mut token = token_in;
+    let mut amounts = vec![amount_in];
+    for pool in path {
+        let pool_amount_in = context
+            .tokens
+            .apply_transfer_tax(token, *amounts.last().unwrap());
+        let amount_out = simulate_pool(
+            pool,
+            token,
+            pool_amount_in,
+            context.tick_store,
+            middleware.clone(),
+        )
+        .await?;
+        amounts.push(amount_out);
+        token = markets::pool_token_out(pool, token);
+    }
+    Ok(amounts)
+}
+
+// Pool with the most output left after paying for the swap's gas, priced in the hop's token out
+// or carried over from its token in, along with what happened to every pool. None when no pool
+// produced any output.
+fn best_simulated_pool<M: 'static + Middleware>(
+    simulations: Vec<(Pool, Result<U256, ExecutorError<M>>)>,
+    token_in: H160,
+    amount_in: U256,
+    gas_model: &GasModel,
+) -> (Option<(Pool, U256)>, Vec<PoolDiagnostic>) {
+    let mut best: Option<(Pool, U256, U256)> = None;
+    let mut diagnostics = vec![];
+
+    for (pool, swap_amount_out) in simulations {
+        let swap_amount_out = match swap_amount_out {
+            Ok(swap_amount_out) => swap_amount_out,
+            Err(error) => {
+                diagnostics.push(PoolDiagnostic::failed(&pool, token_in, &error));
+                continue;
             }
         };
+
+        diagnostics.push(PoolDiagnostic::simulated(&pool, token_in, swap_amount_out));
+        if swap_amount_out.is_zero() {
+            continue;
+        }
+
+        let net_amount_out = gas_model.net_amount_out(
+            &[token_in, markets::pool_token_out(&pool, token_in)],
+            &[amount_in, swap_amount_out],
+            &[pool],
+        );
+        if best.is_none_or(|(_, _, best_net_amount_out)| net_amount_out > best_net_amount_out) {
+            best = Some((pool, swap_amount_out, net_amount_out));
+        }
     }
 
-    Ok((best_pool, best_amount_out))
+    if let Some((best_pool, _, _)) = best {
+        for diagnostic in diagnostics.iter_mut() {
+            if diagnostic.skipped.is_none() && diagnostic.pool != best_pool.address() {
+                diagnostic.skipped = Some(SkipReason::Outranked);
+            }
+        }
+    }
+
+    (
+        best.map(|(pool, amount_out, _)| (pool, amount_out)),
+        diagnostics,
+    )
+}
+
+// Every pool in a market swaps the same pair, so any of them gives the token out
+fn market_token_out(market: &Market, token_in: H160) -> H160 {
+    market
+        .values()
+        .next()
+        .map(|pool| markets::pool_token_out(pool, token_in))
+        .unwrap_or_default()
 }
 
 pub async fn find_a_to_x_to_b_markets_and_route<M: 'static + Middleware>(
@@ -122,38 +400,25 @@ pub async fn find_a_to_x_to_b_markets_and_route<M: 'static + Middleware>(
     token_out: H160,
     token_x: H160,
     configuration: &Config,
+    market_store: &mut MarketStore,
     middleware: Arc<M>,
 ) -> Result<HashMap<U256, markets::Market>, ExecutorError<M>> {
-    let markets = markets::get_market_x(
-        match token_in.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_in,
-        },
-        match token_x.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_x,
-        },
-        &configuration.dexes,
-        middleware.clone(),
-    )
-    .await?;
-    
-    let temp_markets = markets::get_market_x(
-        match token_x.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_x,
-        },
-        match token_out.is_zero() {
-            true => H160::from_str(WETH).unwrap(),
-            false => token_out,
-        },
-        &configuration.dexes,
-        middleware.clone(),
-    )
-    .await?;
+    let token_in = configuration.tokens.resolve(token_in);
+    let token_out = configuration.tokens.resolve(token_out);
+    let token_x = configuration.tokens.resolve(token_x);
+
+    let markets = market_store
+        .get_or_fetch_market(token_in, token_x, &configuration.dexes, middleware.clone())
+        .await?
+        .map(|market| HashMap::from([(markets::get_market_id(token_in, token_x), market)]));
+
+    let temp_markets = market_store
+        .get_or_fetch_market(token_x, token_out, &configuration.dexes, middleware.clone())
+        .await?
+        .map(|market| HashMap::from([(markets::get_market_id(token_x, token_out), market)]));
 
     let result = merge_option_hashmaps(markets, temp_markets);
-    
+
     match result {
         Some(result) => {
             println!("Found markets in A-X-B: {:?}", result.keys());
@@ -174,8 +439,9 @@ pub async fn find_best_a_to_x_to_b_route<M: 'static + Middleware>(
     token_x: H160,
     amount_in: U256,
     simulated_markets: &HashMap<U256, HashMap<H160, Pool>>,
+    context: &QuoteContext<'_>,
     middleware: Arc<M>,
-) -> Result<(Vec<U256>, Vec<U256>, Vec<Pool>), ExecutorError<M>> {
+) -> Result<Route, ExecutorError<M>> {
     let markets_in_route: Vec<&Market> = {
         // Simulate order along route for token_a -> weth -> token_b
         let a_to_x_market = simulated_markets.get(&markets::get_market_id(token_in, token_x));
@@ -194,85 +460,57 @@ pub async fn find_best_a_to_x_to_b_route<M: 'static + Middleware>(
         }
     };
 
-    find_best_route_across_markets(amount_in, token_in, markets_in_route, middleware.clone()).await
+    find_best_route_across_markets(
+        amount_in,
+        token_in,
+        markets_in_route,
+        context,
+        middleware.clone(),
+    )
+    .await
 }
 
-//Returns the amounts in, amount out and a reference to the pools that it took through the route
+//Returns the route through the best pool of each market
 pub async fn find_best_route_across_markets<M: 'static + Middleware>(
     amount_in: U256,
     mut token_in: H160,
     markets: Vec<&Market>,
+    context: &QuoteContext<'_>,
     middleware: Arc<M>,
-) -> Result<(Vec<U256>, Vec<U256>, Vec<Pool>), ExecutorError<M>> {
+) -> Result<Route, ExecutorError<M>> {
+    let route_token_in = token_in;
     let mut amount_in = amount_in;
-    let mut amounts_in: Vec<U256> = vec![];
-    let mut amounts_out: Vec<U256> = vec![];
+    let mut amounts: Vec<U256> = vec![amount_in];
     let mut route: Vec<Pool> = vec![];
+    let mut diagnostics = vec![];
     for market in markets {
-        //TODO: apply tax to amount in
-        let mut best_amount_out = U256::zero();
-        let mut best_pool = Pool::UniswapV2(UniswapV2Pool::default());
-
-        amounts_in.push(amount_in);
-
-        let mut handles = vec![];
-
-        for pool in market.values() {
-            let pool = *pool;
-            match pool {
-                Pool::UniswapV2(_) => {
-                    let swap_amount_out = pool
-                        .simulate_swap(token_in, amount_in, middleware.clone())
-                        .await?;
-                    if swap_amount_out > best_amount_out {
-                        best_amount_out = swap_amount_out;
-                        best_pool = pool;
-                    }
-                }
-
-                Pool::UniswapV3(uniswap_v3_pool) => {
-                    let uniswap_v3_quoter =
-                        IUniswapV3Quoter::new(V3_QUOTER_ADDRESS, middleware.clone());
-
-                    let (token_in, token_out) = if token_in == uniswap_v3_pool.token_a {
-                        (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b)
-                    } else {
-                        (uniswap_v3_pool.token_b, uniswap_v3_pool.token_a)
-                    };
-
-                    handles.push(tokio::spawn(async move {
-                        let swap_amount_out = uniswap_v3_quoter
-                            .quote_exact_input_single(
-                                token_in,
-                                token_out,
-                                pool.fee(),
-                                amount_in,
-                                U256::zero(),
-                            )
-                            .call()
-                            .await?;
-                        Result::<(U256, Pool), ExecutorError<M>>::Ok((swap_amount_out, pool))
-                    }))
-                }
-            };
-        }
+        // Each hop's pool receives the amount after the transfer tax of its token in
+        let pool_amount_in = context.tokens.apply_transfer_tax(token_in, amount_in);
+        let simulations = simulate_market(
+            market,
+            token_in,
+            pool_amount_in,
+            context.tick_store,
+            middleware.clone(),
+        )
+        .await;
+        let (best, market_diagnostics) =
+            best_simulated_pool(simulations, token_in, pool_amount_in, context.gas_model);
+        diagnostics.extend(market_diagnostics);
 
-        for join_result in join_all(handles).await {
-            match join_result {
-                Ok(ok) => {
-                    if let Ok((swap_amount_out, pool)) = ok {
-                        if swap_amount_out > best_amount_out {
-                            best_amount_out = swap_amount_out;
-                            best_pool = pool;
-                        }
-                    }
-                }
-                Err(_err) => {}
+        let (best_pool, best_amount_out) = match best {
+            Some(best) => best,
+            None => {
+                return Err(ExecutorError::NoViableRoute(
+                    token_in,
+                    market_token_out(market, token_in),
+                    diagnostics,
+                ))
             }
-        }
+        };
 
         amount_in = best_amount_out;
-        amounts_out.push(best_amount_out);
+        amounts.push(best_amount_out);
         route.push(best_pool);
 
         //update token in
@@ -296,7 +534,7 @@ pub async fn find_best_route_across_markets<M: 'static + Middleware>(
         };
     }
 
-    Ok((amounts_in, amounts_out, route))
+    Ok(Route::from_amounts(route_token_in, &route, &amounts).with_diagnostics(diagnostics))
 }
 
 pub async fn find_all_markets<M: 'static + Middleware>(
@@ -308,22 +546,23 @@ pub async fn find_all_markets<M: 'static + Middleware>(
     println!("this is the position before call the get_all_markets function\n");
     let markets = markets::get_all_markets(
         configuration.dexes.clone(),
+        configuration.throttle,
+        configuration.checkpoint_path.as_deref(),
         middleware,
     )
     .await?;
 
-    println!("this is the all markets =================> {:?}\n",markets);
-// Ok(markets);
+    println!("this is the all markets =================> {:?}\n", markets);
+    // Ok(markets);
     match markets {
         (markets) => {
             // println!("Found markets: {:?}", markets.keys());
             Ok(markets)
-        }
-        // None => {
-        //     println!("No markets found!");
-        //     Err(ExecutorError::MarketDoesNotExistForPair(
-        //         token_in, token_out,
-        //     ))
-        // }
+        } // None => {
+          //     println!("No markets found!");
+          //     Err(ExecutorError::MarketDoesNotExistForPair(
+          //         token_in, token_out,
+          //     ))
+          // }
     }
-}
\ No newline at end of file
+}
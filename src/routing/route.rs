@@ -0,0 +1,327 @@
+use std::error::Error;
+
+use cfmms::pool::{Pool, UniswapV2Pool, UniswapV3Pool};
+use ethers::types::{H160, U256};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::markets::{
+    fee_fraction, pool_decimals, pool_has_liquidity, pool_token_out, u256_to_f64,
+};
+
+// One swap of a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hop {
+    #[serde(with = "pool_serde")]
+    pub pool: Pool,
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // Share of the input kept by the pool
+    pub fee: f64,
+    // Token out per token in, in whole tokens, before the swap
+    pub spot_price: f64,
+    // Token out per token in, in whole tokens, that the swap actually got
+    pub execution_price: f64,
+    // Share of the spot value lost to moving the price, fees excluded
+    pub price_impact: f64,
+}
+
+impl Hop {
+    pub fn new(pool: Pool, token_in: H160, amount_in: U256, amount_out: U256) -> Hop {
+        let fee = fee_fraction(&pool);
+
+        let spot_price = match pool_has_liquidity(&pool) {
+            true => pool.calculate_price(token_in).unwrap_or(0.0),
+            false => 0.0,
+        };
+
+        let (decimals_in, decimals_out) = pool_decimals(&pool, token_in);
+        let execution_price = match amount_in.is_zero() {
+            true => 0.0,
+            false => {
+                (u256_to_f64(amount_out) / 10_f64.powi(decimals_out as i32))
+                    / (u256_to_f64(amount_in) / 10_f64.powi(decimals_in as i32))
+            }
+        };
+
+        let price_impact = match spot_price > 0.0 && execution_price > 0.0 {
+            true => 1.0 - execution_price / (spot_price * (1.0 - fee)),
+            false => 0.0,
+        };
+
+        Hop {
+            pool,
+            token_in,
+            token_out: pool_token_out(&pool, token_in),
+            amount_in,
+            amount_out,
+            fee,
+            spot_price,
+            execution_price,
+            price_impact,
+        }
+    }
+}
+
+// Why a candidate pool was left out of a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipReason {
+    // The simulation returned an error
+    SimulationFailed,
+    // The pool gave nothing back for the amount in
+    NoOutput,
+    // Another pool of the same market did better after gas
+    Outranked,
+    // The pool can't deliver the amount out of an exact output quote
+    CannotFill,
+}
+
+// What happened to one candidate pool while routing, `skipped` is None for the pool that was used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolDiagnostic {
+    pub pool: H160,
+    pub token_in: H160,
+    // Only quoted by exact output routing
+    #[serde(default)]
+    pub amount_in: Option<U256>,
+    pub amount_out: Option<U256>,
+    pub error: Option<String>,
+    pub skipped: Option<SkipReason>,
+}
+
+impl PoolDiagnostic {
+    pub fn simulated(pool: &Pool, token_in: H160, amount_out: U256) -> PoolDiagnostic {
+        PoolDiagnostic {
+            pool: pool.address(),
+            token_in,
+            amount_in: None,
+            amount_out: Some(amount_out),
+            error: None,
+            skipped: match amount_out.is_zero() {
+                true => Some(SkipReason::NoOutput),
+                false => None,
+            },
+        }
+    }
+
+    // Exact output quote, `amount_in` is None when the pool can't deliver `amount_out`
+    pub fn quoted(
+        pool: &Pool,
+        token_in: H160,
+        amount_in: Option<U256>,
+        amount_out: U256,
+    ) -> PoolDiagnostic {
+        PoolDiagnostic {
+            pool: pool.address(),
+            token_in,
+            amount_in,
+            amount_out: Some(amount_out),
+            error: None,
+            skipped: match amount_in {
+                Some(_) => None,
+                None => Some(SkipReason::CannotFill),
+            },
+        }
+    }
+
+    // Keeps the whole source chain, the top level executor errors only name their variant
+    pub fn failed(pool: &Pool, token_in: H160, error: &dyn Error) -> PoolDiagnostic {
+        let mut message = error.to_string();
+        let mut source = error.source();
+        while let Some(error) = source {
+            message.push_str(&format!(": {error}"));
+            source = error.source();
+        }
+
+        PoolDiagnostic {
+            pool: pool.address(),
+            token_in,
+            amount_in: None,
+            amount_out: None,
+            error: Some(message),
+            skipped: Some(SkipReason::SimulationFailed),
+        }
+    }
+}
+
+// Swaps in order, each hop's amount out is the next hop's amount in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub hops: Vec<Hop>,
+    // Every pool the router looked at for each hop, when the router keeps track of them
+    #[serde(default)]
+    pub diagnostics: Vec<PoolDiagnostic>,
+}
+
+impl Route {
+    // `amounts` holds the amount in followed by the amount out of each pool
+    pub fn from_amounts(token_in: H160, pools: &[Pool], amounts: &[U256]) -> Route {
+        let mut hops = vec![];
+        let mut token = token_in;
+        for (pool, amounts) in pools.iter().zip(amounts.windows(2)) {
+            let hop = Hop::new(*pool, token, amounts[0], amounts[1]);
+            token = hop.token_out;
+            hops.push(hop);
+        }
+
+        Route {
+            token_in,
+            token_out: token,
+            amount_in: amounts[0],
+            amount_out: *amounts.last().unwrap(),
+            hops,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn with_diagnostics(mut self, diagnostics: Vec<PoolDiagnostic>) -> Route {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    pub fn pools(&self) -> Vec<Pool> {
+        self.hops.iter().map(|hop| hop.pool).collect()
+    }
+
+    // The amount in followed by the amount out of each hop
+    pub fn amounts(&self) -> Vec<U256> {
+        let mut amounts = vec![self.amount_in];
+        amounts.extend(self.hops.iter().map(|hop| hop.amount_out));
+        amounts
+    }
+
+    pub fn tokens(&self) -> Vec<H160> {
+        let mut tokens = vec![self.token_in];
+        tokens.extend(self.hops.iter().map(|hop| hop.token_out));
+        tokens
+    }
+
+    // Share of the output lost to price impact over the whole route
+    pub fn price_impact(&self) -> f64 {
+        1.0 - self
+            .hops
+            .iter()
+            .map(|hop| 1.0 - hop.price_impact)
+            .product::<f64>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hops.is_empty()
+    }
+}
+
+// cfmms pools don't implement serde, they're written as a tagged copy of their fields
+mod pool_serde {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "variant")]
+    enum PoolRecord {
+        UniswapV2 {
+            address: H160,
+            token_a: H160,
+            token_a_decimals: u8,
+            token_b: H160,
+            token_b_decimals: u8,
+            reserve_0: u128,
+            reserve_1: u128,
+            fee: u32,
+        },
+        UniswapV3 {
+            address: H160,
+            token_a: H160,
+            token_a_decimals: u8,
+            token_b: H160,
+            token_b_decimals: u8,
+            liquidity: u128,
+            sqrt_price: U256,
+            fee: u32,
+            tick: i32,
+            tick_spacing: i32,
+            liquidity_net: i128,
+        },
+    }
+
+    pub fn serialize<S: Serializer>(pool: &Pool, serializer: S) -> Result<S::Ok, S::Error> {
+        let record = match *pool {
+            Pool::UniswapV2(pool) => PoolRecord::UniswapV2 {
+                address: pool.address,
+                token_a: pool.token_a,
+                token_a_decimals: pool.token_a_decimals,
+                token_b: pool.token_b,
+                token_b_decimals: pool.token_b_decimals,
+                reserve_0: pool.reserve_0,
+                reserve_1: pool.reserve_1,
+                fee: pool.fee,
+            },
+            Pool::UniswapV3(pool) => PoolRecord::UniswapV3 {
+                address: pool.address,
+                token_a: pool.token_a,
+                token_a_decimals: pool.token_a_decimals,
+                token_b: pool.token_b,
+                token_b_decimals: pool.token_b_decimals,
+                liquidity: pool.liquidity,
+                sqrt_price: pool.sqrt_price,
+                fee: pool.fee,
+                tick: pool.tick,
+                tick_spacing: pool.tick_spacing,
+                liquidity_net: pool.liquidity_net,
+            },
+        };
+        record.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pool, D::Error> {
+        Ok(match PoolRecord::deserialize(deserializer)? {
+            PoolRecord::UniswapV2 {
+                address,
+                token_a,
+                token_a_decimals,
+                token_b,
+                token_b_decimals,
+                reserve_0,
+                reserve_1,
+                fee,
+            } => Pool::UniswapV2(UniswapV2Pool::new(
+                address,
+                token_a,
+                token_a_decimals,
+                token_b,
+                token_b_decimals,
+                reserve_0,
+                reserve_1,
+                fee,
+            )),
+            PoolRecord::UniswapV3 {
+                address,
+                token_a,
+                token_a_decimals,
+                token_b,
+                token_b_decimals,
+                liquidity,
+                sqrt_price,
+                fee,
+                tick,
+                tick_spacing,
+                liquidity_net,
+            } => Pool::UniswapV3(UniswapV3Pool::new(
+                address,
+                token_a,
+                token_a_decimals,
+                token_b,
+                token_b_decimals,
+                fee,
+                liquidity,
+                sqrt_price,
+                tick,
+                tick_spacing,
+                liquidity_net,
+            )),
+        })
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashSet, sync::Arc};
+
+use cfmms::pool::Pool;
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ExecutorError,
+    markets::{pool_token_out, Market},
+    routing::{gas::GasModel, route::Route, simulate_path_amounts, QuoteContext},
+};
+
+// Alternative routes considered when splitting an order
+pub const SPLIT_ROUTES: usize = 4;
+
+// The order cut across several routes, each route carries its share of the amount in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitRoute {
+    pub token_in: H160,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub allocations: Vec<Route>,
+}
+
+impl SplitRoute {
+    // Output of every route after its own gas
+    pub fn net_amount_out(&self, gas_model: &GasModel) -> U256 {
+        self.allocations
+            .iter()
+            .map(|allocation| {
+                gas_model.net_amount_out(
+                    &allocation.tokens(),
+                    &allocation.amounts(),
+                    &allocation.pools(),
+                )
+            })
+            .fold(U256::zero(), |a, b| a + b)
+    }
+}
+
+fn token_out(path: &[Pool], token_in: H160) -> H160 {
+    path.iter()
+        .fold(token_in, |token, pool| pool_token_out(pool, token))
+}
+
+// Every pool of a market as a single hop path
+pub fn market_paths(market: &Market) -> Vec<Vec<Pool>> {
+    market.values().map(|pool| vec![*pool]).collect()
+}
+
+// Pools of `routes`, best first, leaving out routes that share a pool with a better one since
+// paths are split as if they didn't move each other's prices
+pub fn disjoint_paths(routes: &[Route]) -> Vec<Vec<Pool>> {
+    let mut used = HashSet::new();
+    let mut paths = vec![];
+    for route in routes {
+        let pools = route.pools();
+        if pools.iter().any(|pool| used.contains(&pool.address())) {
+            continue;
+        }
+        used.extend(pools.iter().map(|pool| pool.address()));
+        paths.push(pools);
+    }
+    paths
+}
+
+// Cuts `amount_in` into `chunks` equal pieces and hands each one to the path with the largest
+// marginal output given what it already carries. Outputs are concave in the input so the greedy
+// fill lands close to the optimal split. Paths are simulated independently, so paths sharing a
+// pool overstate their combined output. Filling stops at the first piece no path can take, the
+// split's amount in is then short of `amount_in`.
+pub async fn find_best_split_route<M: 'static + Middleware>(
+    paths: &[Vec<Pool>],
+    token_in: H160,
+    amount_in: U256,
+    chunks: usize,
+    context: &QuoteContext<'_>,
+    middleware: Arc<M>,
+) -> Result<SplitRoute, ExecutorError<M>> {
+    let chunks = chunks.max(1);
+    let chunk = amount_in / chunks;
+
+    let mut allocated = vec![U256::zero(); paths.len()];
+    let mut outputs = vec![U256::zero(); paths.len()];
+
+    for i in 0..chunks {
+        // The last piece also takes the rounding remainder
+        let piece = match i + 1 == chunks {
+            true => amount_in - chunk * (chunks - 1),
+            false => chunk,
+        };
+        if piece.is_zero() {
+            continue;
+        }
+
+        let mut best: Option<(usize, U256, U256)> = None;
+        for (j, path) in paths.iter().enumerate() {
+            let output = match simulate_path_amounts(
+                path,
+                token_in,
+                allocated[j] + piece,
+                context,
+                middleware.clone(),
+            )
+            .await
+            {
+                Ok(amounts) => *amounts.last().unwrap(),
+                Err(_) => continue,
+            };
+
+            // Opening a new path also pays for its gas
+            let mut gain = output.saturating_sub(outputs[j]);
+            if allocated[j].is_zero() {
+                gain = gain.saturating_sub(context.gas_model.route_gas_cost(
+                    &[token_in, token_out(path, token_in)],
+                    &[piece, output],
+                    path,
+                ));
+            }
+            if best.is_none_or(|(_, _, best_gain)| gain > best_gain) {
+                best = Some((j, output, gain));
+            }
+        }
+
+        match best {
+            Some((j, output, _)) => {
+                allocated[j] += piece;
+                outputs[j] = output;
+            }
+            None => break,
+        }
+    }
+
+    let mut allocations = vec![];
+    for (path, amount) in paths.iter().zip(allocated) {
+        if amount.is_zero() {
+            continue;
+        }
+
+        let amounts =
+            simulate_path_amounts(path, token_in, amount, context, middleware.clone()).await?;
+        allocations.push(Route::from_amounts(token_in, path, &amounts));
+    }
+
+    Ok(SplitRoute {
+        token_in,
+        amount_in: allocations
+            .iter()
+            .map(|allocation| allocation.amount_in)
+            .fold(U256::zero(), |a, b| a + b),
+        amount_out: allocations
+            .iter()
+            .map(|allocation| allocation.amount_out)
+            .fold(U256::zero(), |a, b| a + b),
+        allocations,
+    })
+}
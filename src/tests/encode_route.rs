@@ -0,0 +1,177 @@
+use cfmms::pool::{uniswap_v3::MIN_SQRT_RATIO, Pool, UniswapV2Pool, UniswapV3Pool};
+use ethers::{
+    providers::{Http, Provider},
+    types::{H160, I256, U256},
+};
+use std::str::FromStr;
+
+use crate::{
+    constants::FIFTH_WEB_MULTICALL,
+    routing::{route::Route, split::SplitRoute},
+    tokens::TokenRegistry,
+    transactions::split_multicall,
+};
+
+// Encodes A -V2-> B -V3-> C with a 10% tax on B, checking that the V3 pool is offered what the
+// multicall holds after the tax rather than what the V2 pair sent
+pub async fn try_taxed_route_encoding() -> eyre::Result<()> {
+    let (token_a, token_b, token_c) = (
+        H160::from_low_u64_be(1),
+        H160::from_low_u64_be(2),
+        H160::from_low_u64_be(3),
+    );
+    let v2_pool = UniswapV2Pool::new(
+        H160::from_low_u64_be(4),
+        token_a,
+        18,
+        token_b,
+        18,
+        10u128.pow(24),
+        10u128.pow(24),
+        300,
+    );
+    let v3_pool = UniswapV3Pool {
+        address: H160::from_low_u64_be(5),
+        token_a: token_b,
+        token_a_decimals: 18,
+        token_b: token_c,
+        token_b_decimals: 18,
+        fee: 3000,
+        tick_spacing: 60,
+        ..Default::default()
+    };
+    let route = Route::from_amounts(
+        token_a,
+        &[Pool::UniswapV2(v2_pool), Pool::UniswapV3(v3_pool)],
+        &[U256::from(1000), U256::from(900), U256::from(800)],
+    );
+    let split = SplitRoute {
+        token_in: token_a,
+        amount_in: route.amount_in,
+        amount_out: route.amount_out,
+        allocations: vec![route],
+    };
+
+    let mut tokens = TokenRegistry::new();
+    tokens.set_transfer_tax(token_b, 0.1);
+    let receiver = H160::from_low_u64_be(6);
+    let swap_multicall = split_multicall::<Provider<Http>>(&split, receiver, 0, &tokens)?;
+
+    let v3_calldata =
+        v3_pool.swap_calldata(receiver, true, I256::from(810), MIN_SQRT_RATIO + 1, vec![0]);
+    let v2_calldata = v2_pool.swap_calldata(
+        U256::zero(),
+        U256::from(900),
+        H160::from_str(FIFTH_WEB_MULTICALL)?,
+        vec![],
+    );
+    eyre::ensure!(
+        swap_multicall.calls.len() == 3,
+        "Encoded {} calls",
+        swap_multicall.calls.len()
+    );
+    eyre::ensure!(
+        swap_multicall.calls[1] == (v2_pool.address, format!("0x{}", hex::encode(v2_calldata))),
+        "The V2 pair isn't asked for its quoted output"
+    );
+    eyre::ensure!(
+        swap_multicall.calls[2] == (v3_pool.address, format!("0x{}", hex::encode(v3_calldata))),
+        "The V3 pool isn't offered the taxed amount"
+    );
+    println!(
+        "Encoded the taxed route into {} calls",
+        swap_multicall.calls.len()
+    );
+
+    Ok(())
+}
+
+// Encodes A -V2-> B -V2-> C with 1% slippage, checking that both pairs are asked for less than
+// their quoted output and the last one for the route's minimum
+pub async fn try_slippage_route_encoding() -> eyre::Result<()> {
+    let (token_a, token_b, token_c) = (
+        H160::from_low_u64_be(1),
+        H160::from_low_u64_be(2),
+        H160::from_low_u64_be(3),
+    );
+    let pools = [
+        UniswapV2Pool::new(
+            H160::from_low_u64_be(4),
+            token_a,
+            18,
+            token_b,
+            18,
+            10u128.pow(24),
+            10u128.pow(24),
+            300,
+        ),
+        UniswapV2Pool::new(
+            H160::from_low_u64_be(5),
+            token_b,
+            18,
+            token_c,
+            18,
+            10u128.pow(24),
+            10u128.pow(24),
+            300,
+        ),
+    ];
+    let amount_in = U256::exp10(18);
+    let amount_b = pools[0].simulate_swap(token_a, amount_in);
+    let amounts = [
+        amount_in,
+        amount_b,
+        pools[1].simulate_swap(token_b, amount_b),
+    ];
+    let route = Route::from_amounts(token_a, &pools.map(Pool::UniswapV2), &amounts);
+    let split = SplitRoute {
+        token_in: token_a,
+        amount_in: route.amount_in,
+        amount_out: route.amount_out,
+        allocations: vec![route],
+    };
+
+    let swap_multicall = split_multicall::<Provider<Http>>(
+        &split,
+        H160::from_low_u64_be(6),
+        100,
+        &TokenRegistry::new(),
+    )?;
+    // A transfer to the first pair and a swap on each
+    eyre::ensure!(
+        swap_multicall.calls.len() == 3,
+        "Encoded {} calls",
+        swap_multicall.calls.len()
+    );
+    // token0 is the token in of both pairs, the amount out is the second word
+    let asked = |calldata: &str| -> eyre::Result<U256> {
+        let calldata = hex::decode(calldata.trim_start_matches("0x"))?;
+        Ok(U256::from_big_endian(&calldata[36..68]))
+    };
+    let first_asked = asked(&swap_multicall.calls[1].1)?;
+    let last_asked = asked(&swap_multicall.calls[2].1)?;
+    let amount_out_min = amounts[2] * 99 / 100;
+
+    eyre::ensure!(
+        first_asked < amounts[1],
+        "The first pair is asked for its whole quote {}",
+        first_asked
+    );
+    eyre::ensure!(
+        last_asked <= amount_out_min && amount_out_min - last_asked <= amount_out_min / 100_000_000,
+        "The last pair is asked for {} instead of {}",
+        last_asked,
+        amount_out_min
+    );
+    // The second pair handed the first pair's minimum still makes its own
+    let reached = pools[1].simulate_swap(token_b, first_asked);
+    eyre::ensure!(
+        reached >= last_asked,
+        "The first pair's minimum only buys {} of the {} asked",
+        reached,
+        last_asked
+    );
+    println!("Asked {} and {} with 1% slippage", first_asked, last_asked);
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    env::temp_dir,
+    fs::{metadata, read_dir, remove_file},
+};
+
+use cfmms::pool::{Pool, UniswapV3Pool};
+use ethers::types::{H160, U256};
+
+use crate::markets::{
+    checkpoint::{load_checkpoint, load_checkpoints, write_checkpoint},
+    uniswap_v3::{TickData, TickStore},
+};
+
+// Checks that merging the checkpoints of a directory passes over empty files and keeps the oldest
+// block among the checkpoints holding pools
+pub async fn try_load_checkpoints(dir: &str) -> eyre::Result<()> {
+    let checkpoint_pools = load_checkpoints(dir)?;
+
+    let mut pool_count = 0;
+    let mut block_number: Option<u64> = None;
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let path = path.to_string_lossy();
+        if !path.ends_with(".json") || metadata(path.as_ref())?.len() == 0 {
+            continue;
+        }
+
+        let checkpoint = load_checkpoint(&path)?;
+        pool_count += checkpoint.pools.len();
+        if !checkpoint.pools.is_empty() {
+            block_number = Some(
+                block_number.map_or(checkpoint.block_number, |block_number| {
+                    block_number.min(checkpoint.block_number)
+                }),
+            );
+        }
+    }
+
+    eyre::ensure!(
+        checkpoint_pools.pools.len() == pool_count,
+        "Merged {} pools out of {}",
+        checkpoint_pools.pools.len(),
+        pool_count
+    );
+    eyre::ensure!(
+        block_number.is_none_or(|block_number| checkpoint_pools.block_number == block_number),
+        "Merged checkpoints are at block {} instead of {:?}",
+        checkpoint_pools.block_number,
+        block_number
+    );
+    println!(
+        "Loaded {} pools at block {} from {}",
+        pool_count, checkpoint_pools.block_number, dir
+    );
+
+    Ok(())
+}
+
+// Checks that a V3 pool's tick data is written to the checkpoint and read back with the range of
+// words it was synced over
+pub async fn try_checkpoint_tick_data() -> eyre::Result<()> {
+    let pool = H160::from_low_u64_be(3);
+    let tick_data = TickData {
+        block_number: 100,
+        tick_spacing: 60,
+        tick_bitmap: HashMap::from([(-1, U256::one() << 128), (0, U256::one() << 255)]),
+        ticks: HashMap::from([(-7_680, 10i128.pow(20)), (15_300, -(10i128.pow(20)))]),
+        word_range: Some((-16, 16)),
+    };
+    let mut tick_store = TickStore::new();
+    tick_store.insert(pool, tick_data.clone());
+    let pools = vec![Pool::UniswapV3(UniswapV3Pool {
+        address: pool,
+        tick_spacing: 60,
+        ..Default::default()
+    })];
+
+    let path = temp_dir().join("checkpoint_tick_data.json");
+    let path = path.to_string_lossy();
+    write_checkpoint(&path, &[], &pools, &tick_store, tick_data.block_number)?;
+    let checkpoint = load_checkpoint(&path);
+    remove_file(path.as_ref())?;
+
+    let checkpoint = checkpoint?;
+    let loaded = checkpoint
+        .tick_store
+        .get(pool)
+        .ok_or(eyre::eyre!("No tick data for {:?}", pool))?;
+    eyre::ensure!(
+        loaded.tick_bitmap == tick_data.tick_bitmap
+            && loaded.ticks == tick_data.ticks
+            && loaded.word_range == tick_data.word_range,
+        "Read back {:?} instead of {:?}",
+        loaded,
+        tick_data
+    );
+    println!("Read back the tick data of {:?}", pool);
+
+    Ok(())
+}
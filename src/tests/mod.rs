@@ -1,11 +1,168 @@
 pub(crate) mod discover_erc_4626_vaults;
 pub(crate) mod discover_factories;
+pub(crate) mod encode_route;
+pub(crate) mod load_checkpoints;
+pub(crate) mod negative_cycles;
+pub(crate) mod offline_quote;
+pub(crate) mod offline_swap_calldata;
+pub(crate) mod replay_pool_logs;
+pub(crate) mod route_options;
 pub(crate) mod simulate_swap;
+pub(crate) mod split_route;
 pub(crate) mod swap_calldata;
+pub(crate) mod top_graph_routes;
 pub(crate) mod sync_amms;
+pub(crate) mod trade_size;
+pub(crate) mod v2_amount_in;
+pub(crate) mod v3_quotes;
 
 pub use discover_erc_4626_vaults::*;
 pub use discover_factories::*;
+pub use encode_route::*;
+pub use load_checkpoints::*;
+pub use negative_cycles::*;
+pub use offline_quote::*;
+pub use offline_swap_calldata::*;
+pub use replay_pool_logs::*;
+pub use route_options::*;
 pub use simulate_swap::*;
+pub use split_route::*;
 pub use swap_calldata::*;
+pub use top_graph_routes::*;
 pub use sync_amms::*;
+pub use trade_size::*;
+pub use v2_amount_in::*;
+pub use v3_quotes::*;
+
+use std::{str::FromStr, sync::Arc};
+
+use ethers::{
+    providers::{Http, Provider},
+    types::{H160, U256},
+};
+
+use crate::{
+    constants::WETH,
+    markets::uniswap_v3::TickStore,
+    routing::{gas::GasModel, QuoteContext},
+    tokens::TokenRegistry,
+};
+
+// What the checks quoting from checkpoints alone share. Nothing listens at the endpoint, V2 pools
+// and V3 pools with tick data never reach it.
+pub struct OfflineContext {
+    pub middleware: Arc<Provider<Http>>,
+    pub tokens: TokenRegistry,
+    // 20 gwei, tokens without a rate of their own pay gas carried over from WETH
+    pub gas_model: GasModel,
+}
+
+impl OfflineContext {
+    pub fn quote_context<'a>(&'a self, tick_store: &'a TickStore) -> QuoteContext<'a> {
+        QuoteContext {
+            tokens: &self.tokens,
+            tick_store,
+            gas_model: &self.gas_model,
+            v3_quoter: None,
+        }
+    }
+}
+
+pub fn offline_context() -> eyre::Result<OfflineContext> {
+    Ok(OfflineContext {
+        middleware: Arc::new(Provider::<Http>::try_from("http://127.0.0.1:8545")?),
+        tokens: TokenRegistry::new(),
+        gas_model: GasModel::new(U256::from(20_000_000_000u64), H160::from_str(WETH)?),
+    })
+}
+
+// The checks that need no endpoint, run from the crate root where the checkpoints and fixtures are
+#[cfg(test)]
+mod offline {
+    const CHECKPOINT: &str = "checkpoints/sushiswap.json";
+
+    #[tokio::test]
+    async fn offline_quote() -> eyre::Result<()> {
+        super::try_offline_quote(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn offline_swap_calldata() -> eyre::Result<()> {
+        super::try_offline_swap_calldata(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn route_options() -> eyre::Result<()> {
+        super::try_route_options(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn split_route() -> eyre::Result<()> {
+        super::try_split_route(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn top_graph_routes() -> eyre::Result<()> {
+        super::try_top_graph_routes(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn trade_size() -> eyre::Result<()> {
+        super::try_trade_size(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn negative_cycle_search() -> eyre::Result<()> {
+        super::try_negative_cycle_search(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn v2_amount_in() -> eyre::Result<()> {
+        super::try_v2_amount_in(CHECKPOINT).await
+    }
+
+    #[tokio::test]
+    async fn load_checkpoints() -> eyre::Result<()> {
+        super::try_load_checkpoints("checkpoints").await
+    }
+
+    #[tokio::test]
+    async fn checkpoint_tick_data() -> eyre::Result<()> {
+        super::try_checkpoint_tick_data().await
+    }
+
+    #[tokio::test]
+    async fn replay_pool_logs() -> eyre::Result<()> {
+        super::try_replay_pool_logs(CHECKPOINT, "fixtures/sushiswap_reorg_logs.json").await
+    }
+
+    #[tokio::test]
+    async fn journal_rollback() -> eyre::Result<()> {
+        super::try_journal_rollback(CHECKPOINT, "fixtures/sushiswap_sync_logs.json").await
+    }
+
+    #[tokio::test]
+    async fn v3_liquidity_logs() -> eyre::Result<()> {
+        super::try_v3_liquidity_logs().await
+    }
+
+    #[tokio::test]
+    async fn v3_swap_against_amms() -> eyre::Result<()> {
+        super::try_v3_swap_against_amms().await
+    }
+
+    #[tokio::test]
+    async fn v3_swap_word_range() -> eyre::Result<()> {
+        super::try_v3_swap_word_range().await
+    }
+
+    #[tokio::test]
+    async fn taxed_route_encoding() -> eyre::Result<()> {
+        super::try_taxed_route_encoding().await
+    }
+
+    #[tokio::test]
+    async fn slippage_route_encoding() -> eyre::Result<()> {
+        super::try_slippage_route_encoding().await
+    }
+}
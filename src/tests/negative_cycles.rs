@@ -0,0 +1,172 @@
+use cfmms::pool::{Pool, UniswapV2Pool};
+use ethers::types::H160;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arbitrage::{find_cycles, negative_cycle::LogPriceGraph, Cycle},
+    constants::*,
+    markets::{checkpoint::load_checkpoint, pool_token_out},
+    routing::graph::TokenGraph,
+};
+
+// The search is meant to finish well within this on tens of thousands of pools, in a release build
+const MAX_SEARCH_TIME: Duration = Duration::from_secs(1);
+
+// Deterministic stand-in for a random source, the same pools are generated on every run
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        self.next() as f64 / (1u64 << 31) as f64
+    }
+}
+
+// Times the negative cycle search from WETH over the pools of a checkpoint, topped up with
+// synthetic V2 pools to `pool_count`. Synthetic pools price their tokens consistently, give or
+// take less than a fee, so like real pools only a few of them form cycles.
+pub async fn try_negative_cycle_benchmark(
+    checkpoint_path: &str,
+    pool_count: usize,
+) -> eyre::Result<()> {
+    let mut pools = load_checkpoint(checkpoint_path)?.pools;
+
+    // Synthetic tokens only meet the checkpoint's tokens at WETH, so their made up prices don't
+    // contradict the checkpoint's
+    let synthetic_tokens = pool_count.saturating_sub(pools.len()) / 4;
+    let mut tokens = vec![H160::from_str(WETH)?];
+    tokens.extend((1..=synthetic_tokens as u64).map(H160::from_low_u64_be));
+
+    let mut rng = Lcg(0x5eed);
+    let prices: Vec<f64> = tokens
+        .iter()
+        .map(|_| 10_f64.powf(rng.next_f64() * 6.0 - 3.0))
+        .collect();
+
+    let mut address = 1u64 << 40;
+    while pools.len() < pool_count {
+        let a = rng.next() as usize % tokens.len();
+        let b = rng.next() as usize % tokens.len();
+        if a == b {
+            continue;
+        }
+
+        let reserve_a = 10_f64.powf(18.0 + rng.next_f64() * 6.0);
+        let mispricing = 1.0 + (rng.next_f64() - 0.5) * 0.004;
+        let reserve_b = reserve_a * prices[a] / prices[b] * mispricing;
+
+        address += 1;
+        pools.push(Pool::UniswapV2(UniswapV2Pool::new(
+            H160::from_low_u64_be(address),
+            tokens[a],
+            18,
+            tokens[b],
+            18,
+            reserve_a as u128,
+            reserve_b as u128,
+            300,
+        )));
+    }
+
+    let start = Instant::now();
+    let graph = TokenGraph::new(&pools);
+    let graph_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut log_price_graph = LogPriceGraph::new(&graph);
+    let cycles = log_price_graph.find_negative_cycles(&[H160::from_str(WETH)?]);
+    let search_time = start.elapsed();
+
+    println!(
+        "{} pools, {} tokens, {} edges: graph built in {:?}, {} cycles found in {:?} ({} build)",
+        graph.pool_count(),
+        log_price_graph.token_count(),
+        log_price_graph.edge_count(),
+        graph_time,
+        cycles.len(),
+        search_time,
+        match cfg!(debug_assertions) {
+            true => "debug",
+            false => "release",
+        }
+    );
+
+    // Debug builds run the search several times slower
+    eyre::ensure!(
+        cfg!(debug_assertions) || search_time < MAX_SEARCH_TIME,
+        "Negative cycle search took {:?} on {} pools",
+        search_time,
+        graph.pool_count()
+    );
+    check_cycles(&cycles, H160::from_str(WETH)?)?;
+
+    Ok(())
+}
+
+// Every cycle starts and ends at `start`, each pool trades the tokens on either side of it and
+// the spot rates multiply to more than one after fees
+fn check_cycles(cycles: &[Cycle], start: H160) -> eyre::Result<()> {
+    for cycle in cycles {
+        eyre::ensure!(
+            cycle.start_token() == start && cycle.tokens.last() == Some(&start),
+            "Cycle {:?} doesn't start and end at {:?}",
+            cycle.tokens,
+            start
+        );
+        for (pool, tokens) in cycle.pools.iter().zip(cycle.tokens.windows(2)) {
+            eyre::ensure!(
+                pool_token_out(pool, tokens[0]) == tokens[1],
+                "Pool {:?} doesn't trade {:?} for {:?}",
+                pool.address(),
+                tokens[0],
+                tokens[1]
+            );
+        }
+        eyre::ensure!(
+            cycle.spot_return() > 1.0,
+            "Cycle {:?} returns {} at spot",
+            cycle.tokens,
+            cycle.spot_return()
+        );
+    }
+    Ok(())
+}
+
+// Runs the negative cycle search from WETH over the pools of a checkpoint and checks its cycles
+// against the 2 and 3 pool cycles enumerated directly
+pub async fn try_negative_cycle_search(checkpoint_path: &str) -> eyre::Result<()> {
+    let weth = H160::from_str(WETH)?;
+    let graph = TokenGraph::new(&load_checkpoint(checkpoint_path)?.pools);
+
+    let cycles = LogPriceGraph::new(&graph).find_negative_cycles(&[weth]);
+    check_cycles(&cycles, weth)?;
+
+    let enumerated: Vec<Cycle> = find_cycles(&graph, &[weth], 3)
+        .into_iter()
+        .filter(|cycle| cycle.spot_return() > 1.0)
+        .collect();
+    println!(
+        "{} negative cycles found, {} profitable 2 and 3 pool cycles enumerated",
+        cycles.len(),
+        enumerated.len()
+    );
+
+    eyre::ensure!(
+        enumerated.is_empty() || !cycles.is_empty(),
+        "The search found no cycle where enumeration found {}",
+        enumerated.len()
+    );
+
+    Ok(())
+}
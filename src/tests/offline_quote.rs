@@ -0,0 +1,60 @@
+use ethers::types::{H160, U256};
+use std::str::FromStr;
+
+use crate::{
+    constants::*,
+    markets::checkpoint::load_checkpoint,
+    routing::{
+        exact_output::find_best_route_across_markets_exact_output, find_best_route_across_markets,
+    },
+    tests::offline_context,
+};
+
+// Quotes 1 WETH -> USDT, and WETH -> exactly 1000 USDT, from the pools of a checkpoint alone
+pub async fn try_offline_quote(checkpoint_path: &str) -> eyre::Result<()> {
+    let offline = offline_context()?;
+    let middleware = offline.middleware.clone();
+
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let market_store = checkpoint_pools.market_store();
+    println!(
+        "Loaded {} pools at block {}",
+        market_store.len(),
+        checkpoint_pools.block_number
+    );
+
+    let token_in = H160::from_str(WETH)?;
+    let token_out = H160::from_str("0xdac17f958d2ee523a2206206994597c13d831ec7")?; // USDT
+    let market = market_store
+        .get_market(token_in, token_out)
+        .ok_or_else(|| eyre::eyre!("No WETH/USDT market in {}", checkpoint_path))?;
+
+    let context = offline.quote_context(&checkpoint_pools.tick_store);
+
+    let route = find_best_route_across_markets(
+        U256::exp10(18),
+        token_in,
+        vec![market],
+        &context,
+        middleware.clone(),
+    )
+    .await?;
+
+    println!("Route: {}", serde_json::to_string_pretty(&route)?);
+
+    let exact_output_route = find_best_route_across_markets_exact_output(
+        U256::from(1_000_000_000u64),
+        token_in,
+        vec![market],
+        &context,
+        middleware,
+    )
+    .await?;
+
+    println!(
+        "Exact output route: {}",
+        serde_json::to_string_pretty(&exact_output_route)?
+    );
+
+    Ok(())
+}
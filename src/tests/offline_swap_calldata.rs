@@ -0,0 +1,99 @@
+use ethers::types::{H160, U256};
+use std::str::FromStr;
+
+use crate::{
+    config::{Chain, Config},
+    constants::*,
+    markets::checkpoint::load_checkpoint,
+    routing::RouteOptions,
+    tests::offline_context,
+    tokens::TokenRegistry,
+    transactions::{
+        swap_transaction_calldata, swap_transaction_calldata_exact_output, types::SwapRequest,
+    },
+};
+
+// Builds exact input and exact output calldata for 1000 DAI -> USDT from the pools of a
+// checkpoint alone, routing through the chain's stables and WETH. The exact output swap pays its
+// V2 first pair the whole maximum in.
+pub async fn try_offline_swap_calldata(checkpoint_path: &str) -> eyre::Result<()> {
+    // There are no dexes to fetch missing markets from and the gas price is fixed, so every quote
+    // comes from the checkpoint
+    let middleware = offline_context()?.middleware;
+
+    let weth = H160::from_str(WETH)?;
+    let configuration = Config {
+        chain: Chain::Ethereum,
+        tokens: TokenRegistry::for_chain(Chain::Ethereum),
+        weth_address: weth,
+        weth_decimals: 18,
+        gas_price: Some(U256::from(20_000_000_000u64)),
+        intermediate_tokens: vec![
+            weth,
+            H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?, // USDC
+        ],
+        ..Default::default()
+    };
+    let route_options = RouteOptions::from_config(&configuration);
+
+    let mut checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let mut market_store = checkpoint_pools.market_store();
+
+    let request = SwapRequest {
+        token_in: H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f")?, // DAI
+        token_out: H160::from_str("0xdac17f958d2ee523a2206206994597c13d831ec7")?, // USDT
+        amount: U256::exp10(21),
+        slippage: 50,
+        receiver: H160::from_low_u64_be(1),
+    };
+
+    let (swap_data, swap_multicall) = swap_transaction_calldata(
+        &configuration,
+        &request,
+        &route_options,
+        &mut checkpoint_pools.tick_store,
+        &mut market_store,
+        middleware.clone(),
+    )
+    .await?;
+    println!("SwapData: {:?}\n\nSwapMultiCall: {:?}\n", swap_data, swap_multicall);
+    eyre::ensure!(
+        !swap_multicall.calls.is_empty()
+            && swap_data.amount_out_min.is_some_and(|amount| !amount.is_zero()),
+        "No exact input route for {:?}",
+        request
+    );
+
+    let request = SwapRequest {
+        amount: U256::from(1_000_000_000u64),
+        ..request
+    };
+    let (swap_data, swap_multicall) = swap_transaction_calldata_exact_output(
+        &configuration,
+        &request,
+        &route_options,
+        &mut checkpoint_pools.tick_store,
+        &mut market_store,
+        middleware,
+    )
+    .await?;
+    println!("SwapData: {:?}\n\nSwapMultiCall: {:?}", swap_data, swap_multicall);
+    let Some(amount_in_max) = swap_data.amount_in_max else {
+        eyre::bail!("No exact output route for {:?}", request);
+    };
+    // The first pair is V2 and gets the whole maximum, the last word of the transfer
+    let transfer_amount = swap_multicall
+        .calls
+        .first()
+        .and_then(|(_, calldata)| hex::decode(calldata.trim_start_matches("0x")).ok())
+        .filter(|calldata| calldata.len() >= 32)
+        .map(|calldata| U256::from_big_endian(&calldata[calldata.len() - 32..]));
+    eyre::ensure!(
+        transfer_amount == Some(amount_in_max),
+        "The first pair is paid {:?} instead of the maximum {}",
+        transfer_amount,
+        amount_in_max
+    );
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use cfmms::pool::{Pool, UniswapV3Pool};
+use ethers::{
+    abi::{encode, Token},
+    contract::EthEvent,
+    types::{Log, H160, H256, I256, U256, U64},
+};
+use std::{fs::read_to_string, str::FromStr, sync::Arc};
+use tokio::sync::RwLock;
+
+use crate::{
+    abi::{BurnFilter, MintFilter},
+    markets::{
+        checkpoint::load_checkpoint,
+        journal::DEFAULT_REORG_DEPTH,
+        state_sync::{apply_logs, sync_pool_state, PoolState},
+        store::MarketStore,
+        uniswap_v3::{TickData, TickStore},
+    },
+};
+
+// Replays recorded Sync/Swap logs over the pools of a checkpoint, the same path the WebSocket
+// subscription takes. Removed logs in the fixture roll their block back.
+pub async fn try_replay_pool_logs(checkpoint_path: &str, logs_path: &str) -> eyre::Result<()> {
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let pool_state = Arc::new(RwLock::new(PoolState::new(
+        checkpoint_pools.market_store(),
+        checkpoint_pools.block_number,
+    )));
+
+    let logs: Vec<Log> = serde_json::from_str(&read_to_string(logs_path)?)?;
+
+    // WETH/USDT on sushiswap, the pool the fixture's logs are from
+    let pool = H160::from_str("0x06da0fd433c1a5d7a4faa01111c044910a184553")?;
+    println!(
+        "Before: {:?}",
+        pool_state.read().await.market_store.get_pool(pool)
+    );
+
+    let last_block = sync_pool_state(pool_state.clone(), futures::stream::iter(logs)).await;
+    println!(
+        "Applied logs up to block {:?}, last safe block {}",
+        last_block,
+        pool_state.read().await.last_safe_block()
+    );
+
+    println!(
+        "After: {:?}",
+        pool_state.read().await.market_store.get_pool(pool)
+    );
+
+    Ok(())
+}
+
+// Applies the recorded Sync logs over a checkpoint and rolls the journal back one block at a time,
+// checking that the pool gets back the reserves it had before each block
+pub async fn try_journal_rollback(checkpoint_path: &str, logs_path: &str) -> eyre::Result<()> {
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let mut pool_state = PoolState::new(
+        checkpoint_pools.market_store(),
+        checkpoint_pools.block_number,
+    );
+    let logs: Vec<Log> = serde_json::from_str(&read_to_string(logs_path)?)?;
+
+    // WETH/USDT on sushiswap, the pool the fixture's logs are from
+    let pool = H160::from_str("0x06da0fd433c1a5d7a4faa01111c044910a184553")?;
+    let reserves = |pool_state: &PoolState| match pool_state.market_store.get_pool(pool) {
+        Some(Pool::UniswapV2(pool)) => Some((pool.reserve_0, pool.reserve_1)),
+        _ => None,
+    };
+
+    // Reserves before each block of the fixture, oldest first
+    let mut block_numbers: Vec<u64> = logs
+        .iter()
+        .filter_map(|log| log.block_number.map(|block_number| block_number.as_u64()))
+        .collect();
+    block_numbers.dedup();
+    let mut snapshots = vec![];
+    let mut replayed = pool_state.clone();
+    for block_number in &block_numbers {
+        snapshots.push(reserves(&replayed));
+        let block_logs = logs
+            .iter()
+            .filter(|log| log.block_number == Some(U64::from(*block_number)))
+            .cloned()
+            .collect();
+        apply_logs(&mut replayed, block_logs);
+    }
+
+    apply_logs(&mut pool_state, logs);
+    eyre::ensure!(
+        reserves(&pool_state) != snapshots[0],
+        "The logs left {:?} unchanged",
+        pool
+    );
+
+    for (block_number, snapshot) in block_numbers.iter().zip(snapshots).rev() {
+        let rolled_back = pool_state
+            .journal
+            .rollback(&mut pool_state.market_store, *block_number);
+        eyre::ensure!(
+            rolled_back == Some(*block_number),
+            "Rolling back block {} gave {:?}",
+            block_number,
+            rolled_back
+        );
+        eyre::ensure!(
+            reserves(&pool_state) == snapshot,
+            "Reserves are {:?} instead of {:?} after rolling back block {}",
+            reserves(&pool_state),
+            snapshot,
+            block_number
+        );
+    }
+    eyre::ensure!(
+        pool_state.journal.head().is_none(),
+        "Blocks left in the journal after rolling everything back"
+    );
+
+    // The chain head moving on makes blocks final without any pool changing
+    let last_block = *block_numbers.last().unwrap();
+    pool_state
+        .journal
+        .advance(last_block + DEFAULT_REORG_DEPTH as u64);
+    eyre::ensure!(
+        pool_state.last_safe_block() == last_block,
+        "Last safe block is {} instead of {}",
+        pool_state.last_safe_block(),
+        last_block
+    );
+    println!("Rolled back {} blocks", block_numbers.len());
+
+    Ok(())
+}
+
+// Applies Mint and Burn logs to a V3 pool at tick 0: ranges holding the tick move its liquidity,
+// others don't, a log delivered twice counts once, and the pool's tick data is dropped for a resync
+pub async fn try_v3_liquidity_logs() -> eyre::Result<()> {
+    let address = H160::from_low_u64_be(1);
+    let pool = UniswapV3Pool {
+        address,
+        token_a: H160::from_low_u64_be(2),
+        token_b: H160::from_low_u64_be(3),
+        liquidity: 1_000,
+        tick_spacing: 60,
+        ..Default::default()
+    };
+    let mut pool_state = PoolState::new(MarketStore::from_pools(vec![Pool::UniswapV3(pool)]), 0);
+    let mut tick_store = TickStore::new();
+    tick_store.insert(address, TickData::default());
+    pool_state.tick_store = tick_store;
+
+    let logs = vec![
+        liquidity_log(address, MintFilter::signature(), -60, 60, 500, 0),
+        // Delivered again by the subscription
+        liquidity_log(address, MintFilter::signature(), -60, 60, 500, 0),
+        liquidity_log(address, MintFilter::signature(), 60, 120, 300, 1),
+        liquidity_log(address, BurnFilter::signature(), -120, 60, 200, 2),
+        liquidity_log(address, BurnFilter::signature(), -120, -60, 100, 3),
+    ];
+    apply_logs(&mut pool_state, logs);
+
+    let Some(Pool::UniswapV3(pool)) = pool_state.market_store.get_pool(address).copied() else {
+        eyre::bail!("Pool {:?} is gone", address);
+    };
+    eyre::ensure!(
+        pool.liquidity == 1_300,
+        "Liquidity is {} instead of 1300",
+        pool.liquidity
+    );
+    eyre::ensure!(
+        pool_state.tick_store.get(address).is_none(),
+        "Tick data of {:?} kept after its liquidity moved",
+        address
+    );
+    println!("Liquidity after Mint and Burn: {}", pool.liquidity);
+
+    Ok(())
+}
+
+// Mint and Burn share the indexed range, Mint has the sender in front of the amounts
+fn liquidity_log(
+    address: H160,
+    signature: H256,
+    tick_lower: i32,
+    tick_upper: i32,
+    amount: u128,
+    log_index: u64,
+) -> Log {
+    let tick_topic = |tick: i32| {
+        let mut topic = [0u8; 32];
+        I256::from(tick).into_raw().to_big_endian(&mut topic);
+        H256(topic)
+    };
+    let mut data = vec![];
+    if signature == MintFilter::signature() {
+        data.push(Token::Address(H160::zero()));
+    }
+    data.extend([
+        Token::Uint(U256::from(amount)),
+        Token::Uint(U256::zero()),
+        Token::Uint(U256::zero()),
+    ]);
+
+    Log {
+        address,
+        topics: vec![
+            signature,
+            H256::zero(),
+            tick_topic(tick_lower),
+            tick_topic(tick_upper),
+        ],
+        data: encode(&data).into(),
+        block_number: Some(U64::from(1)),
+        block_hash: Some(H256::from_low_u64_be(1)),
+        log_index: Some(U256::from(log_index)),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,92 @@
+use ethers::types::{H160, U256};
+use std::str::FromStr;
+
+use crate::{
+    config::{Chain, Config},
+    constants::*,
+    markets::checkpoint::load_checkpoint,
+    routing::{find_best_path_route, RouteOptions},
+    tests::offline_context,
+    tokens::TokenRegistry,
+    transactions::find_route,
+};
+
+// Checks that enumerated paths only hop through connectors and never swap a skipped pair, and
+// that the route built from them for 1000 DAI -> USDT on a checkpoint keeps to both
+pub async fn try_route_options(checkpoint_path: &str) -> eyre::Result<()> {
+    // There are no dexes to fetch missing markets from
+    let offline = offline_context()?;
+
+    let weth = H160::from_str(WETH)?;
+    let usdc = H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?;
+    let dai = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f")?;
+    let usdt = H160::from_str("0xdac17f958d2ee523a2206206994597c13d831ec7")?;
+
+    let configuration = Config {
+        chain: Chain::Ethereum,
+        tokens: TokenRegistry::for_chain(Chain::Ethereum),
+        weth_address: weth,
+        weth_decimals: 18,
+        intermediate_tokens: vec![H160::zero(), usdc],
+        skip_pairs: vec![(dai, H160::zero())],
+        ..Default::default()
+    };
+    let route_options = RouteOptions::from_config(&configuration);
+    eyre::ensure!(
+        route_options.connectors == vec![weth, usdc] && route_options.is_skipped(weth, dai),
+        "Native token not resolved in {:?}",
+        route_options
+    );
+
+    let paths = find_route(dai, usdt, &route_options);
+    eyre::ensure!(!paths.is_empty(), "No path from DAI to USDT");
+    for path in &paths {
+        check_path(path, &route_options)?;
+    }
+    println!("Paths: {:?}", paths);
+
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let mut market_store = checkpoint_pools.market_store();
+    let context = offline.quote_context(&checkpoint_pools.tick_store);
+
+    let route = find_best_path_route(
+        &paths,
+        U256::exp10(21),
+        &configuration,
+        &mut market_store,
+        &context,
+        offline.middleware.clone(),
+    )
+    .await?;
+    check_path(&route.tokens(), &route_options)?;
+    println!("Route: {:?} -> {}", route.tokens(), route.amount_out);
+
+    Ok(())
+}
+
+fn check_path(path: &[H160], route_options: &RouteOptions) -> eyre::Result<()> {
+    eyre::ensure!(
+        path.len() >= 2 && path.len() - 1 <= route_options.max_hops,
+        "Path {:?} is longer than {} hops",
+        path,
+        route_options.max_hops
+    );
+    for token in &path[1..path.len() - 1] {
+        eyre::ensure!(
+            route_options.connectors.contains(token),
+            "Path {:?} hops through {:?}, which isn't a connector",
+            path,
+            token
+        );
+    }
+    for pair in path.windows(2) {
+        eyre::ensure!(
+            !route_options.is_skipped(pair[0], pair[1]),
+            "Path {:?} swaps the skipped pair {:?}",
+            path,
+            pair
+        );
+    }
+
+    Ok(())
+}
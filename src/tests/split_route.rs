@@ -0,0 +1,113 @@
+use cfmms::pool::Pool;
+use ethers::{
+    providers::{Http, Provider},
+    types::{H160, U256},
+};
+use std::str::FromStr;
+
+use crate::{
+    config::DEFAULT_SPLIT_PRICE_IMPACT,
+    constants::*,
+    markets::{checkpoint::load_checkpoint, store::MarketStore},
+    routing::{
+        find_best_route_across_markets,
+        split::{find_best_split_route, market_paths},
+    },
+    tests::offline_context,
+    transactions::split_multicall,
+};
+
+// Splits 1000 WETH -> USDT across the checkpoint's WETH/USDT pool and a copy of it at another
+// address, checking that the order lands about evenly on both, nets more than either pool alone
+// and encodes a swap for each
+pub async fn try_split_route(checkpoint_path: &str) -> eyre::Result<()> {
+    let offline = offline_context()?;
+    let middleware = offline.middleware.clone();
+
+    let token_in = H160::from_str(WETH)?;
+    let token_out = H160::from_str("0xdac17f958d2ee523a2206206994597c13d831ec7")?; // USDT
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let Some(Pool::UniswapV2(pool)) = checkpoint_pools
+        .market_store()
+        .get_market(token_in, token_out)
+        .and_then(|market| market.values().next().copied())
+    else {
+        eyre::bail!("No WETH/USDT V2 pool in {}", checkpoint_path);
+    };
+    let mut copy = pool;
+    copy.address = H160::from_low_u64_be(1);
+    let market_store = MarketStore::from_pools(vec![Pool::UniswapV2(pool), Pool::UniswapV2(copy)]);
+    let market = market_store.get_market(token_in, token_out).unwrap();
+
+    let context = offline.quote_context(&checkpoint_pools.tick_store);
+    let gas_model = &offline.gas_model;
+    let amount_in = U256::exp10(21);
+
+    let route = find_best_route_across_markets(
+        amount_in,
+        token_in,
+        vec![market],
+        &context,
+        middleware.clone(),
+    )
+    .await?;
+    eyre::ensure!(
+        route.price_impact() > DEFAULT_SPLIT_PRICE_IMPACT,
+        "1000 WETH only moves the price by {}",
+        route.price_impact()
+    );
+
+    let split = find_best_split_route(
+        &market_paths(market),
+        token_in,
+        amount_in,
+        10,
+        &context,
+        middleware,
+    )
+    .await?;
+    eyre::ensure!(
+        split.allocations.len() == 2,
+        "Split over {} pools",
+        split.allocations.len()
+    );
+    for allocation in &split.allocations {
+        eyre::ensure!(
+            allocation.amount_in * 10 >= amount_in * 4,
+            "{} of {} went to {:?}",
+            allocation.amount_in,
+            amount_in,
+            allocation.pools()[0].address()
+        );
+    }
+    let route_net_amount_out =
+        gas_model.net_amount_out(&route.tokens(), &route.amounts(), &route.pools());
+    eyre::ensure!(
+        split.amount_in == amount_in && split.net_amount_out(gas_model) > route_net_amount_out,
+        "Split nets {} for {}, one pool {}",
+        split.net_amount_out(gas_model),
+        split.amount_in,
+        route_net_amount_out
+    );
+
+    let swap_multicall =
+        split_multicall::<Provider<Http>>(&split, H160::from_low_u64_be(2), 50, &offline.tokens)?;
+    // A transfer to each pair and its swap
+    eyre::ensure!(
+        swap_multicall.calls.len() == 4,
+        "Encoded {} calls",
+        swap_multicall.calls.len()
+    );
+    println!(
+        "Split {:?} -> {} instead of {}",
+        split
+            .allocations
+            .iter()
+            .map(|allocation| allocation.amount_in)
+            .collect::<Vec<_>>(),
+        split.amount_out,
+        route.amount_out
+    );
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use ethers::types::{H160, U256};
+use std::{collections::HashSet, str::FromStr};
+
+use crate::{
+    config::DEFAULT_SPLIT_PRICE_IMPACT,
+    markets::checkpoint::load_checkpoint,
+    routing::{
+        graph::{find_best_graph_route, find_top_graph_routes, TokenGraph},
+        RouteOptions,
+    },
+    tests::offline_context,
+};
+
+// Routes kept for 1000 DAI -> USDT
+const TOP_ROUTES: usize = 3;
+
+// Checks that the graph router returns distinct routes for 1000 DAI -> USDT on a checkpoint, best
+// first after gas, and that keeping more partial routes per token never does worse than one
+pub async fn try_top_graph_routes(checkpoint_path: &str) -> eyre::Result<()> {
+    let offline = offline_context()?;
+    let middleware = offline.middleware.clone();
+
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let graph = TokenGraph::new(&checkpoint_pools.pools);
+
+    let token_in = H160::from_str("0x6b175474e89094c44da98b954eedeac495271d0f")?; // DAI
+    let token_out = H160::from_str("0xdac17f958d2ee523a2206206994597c13d831ec7")?; // USDT
+    let amount_in = U256::exp10(21);
+
+    let context = offline.quote_context(&checkpoint_pools.tick_store);
+    let gas_model = &offline.gas_model;
+    let route_options = RouteOptions {
+        connectors: vec![],
+        skip_pairs: vec![],
+        max_hops: 3,
+        beam_width: 8,
+        split_chunks: 1,
+        split_price_impact: DEFAULT_SPLIT_PRICE_IMPACT,
+    };
+
+    let routes = find_top_graph_routes(
+        &graph,
+        token_in,
+        token_out,
+        amount_in,
+        TOP_ROUTES,
+        &route_options,
+        &context,
+        middleware.clone(),
+    )
+    .await?;
+    eyre::ensure!(
+        routes.len() == TOP_ROUTES,
+        "Found {} routes instead of {}",
+        routes.len(),
+        TOP_ROUTES
+    );
+
+    let net_amounts_out: Vec<U256> = routes
+        .iter()
+        .map(|route| gas_model.net_amount_out(&route.tokens(), &route.amounts(), &route.pools()))
+        .collect();
+    eyre::ensure!(
+        net_amounts_out.windows(2).all(|pair| pair[0] >= pair[1]),
+        "Routes aren't best first: {:?}",
+        net_amounts_out
+    );
+
+    let mut seen = HashSet::new();
+    for route in &routes {
+        let pools: Vec<H160> = route.pools().iter().map(|pool| pool.address()).collect();
+        eyre::ensure!(seen.insert(pools.clone()), "Route {:?} is repeated", pools);
+        println!("Route: {:?} -> {}", route.tokens(), route.amount_out);
+    }
+
+    let best_route = find_best_graph_route(
+        &graph,
+        token_in,
+        token_out,
+        amount_in,
+        &route_options,
+        &context,
+        middleware,
+    )
+    .await?
+    .ok_or_else(|| eyre::eyre!("No graph route from DAI to USDT"))?;
+    let best_net_amount_out = gas_model.net_amount_out(
+        &best_route.tokens(),
+        &best_route.amounts(),
+        &best_route.pools(),
+    );
+    eyre::ensure!(
+        net_amounts_out[0] >= best_net_amount_out,
+        "Keeping {} partial routes per token nets {}, one nets {}",
+        TOP_ROUTES,
+        net_amounts_out[0],
+        best_net_amount_out
+    );
+
+    Ok(())
+}
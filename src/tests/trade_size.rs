@@ -0,0 +1,129 @@
+use ethers::types::{H160, U256};
+use std::str::FromStr;
+
+use crate::{
+    arbitrage::{
+        find_cycles,
+        sizing::{golden_section_search, optimal_trade_size},
+    },
+    constants::*,
+    markets::{checkpoint::load_checkpoint, f64_to_u256, u256_to_f64},
+    routing::{graph::TokenGraph, simulate_path_amounts},
+    tests::offline_context,
+};
+
+// Below this many units on any hop the pools' rounding to whole units outweighs the curvature
+// of the profit, e.g. a few units of a 6 decimal token worth a sizeable share of the profit
+const MIN_CHECKED_HOP_AMOUNT: u64 = 1_000_000_000;
+
+// Sizes the profitable WETH cycles of a checkpoint and checks that no input around the returned
+// one does better, and that the golden section search the V3 cycles use lands on the same profit
+// as the closed form V2 optimum
+pub async fn try_trade_size(checkpoint_path: &str) -> eyre::Result<()> {
+    let offline = offline_context()?;
+    let middleware = offline.middleware.clone();
+
+    let weth = H160::from_str(WETH)?;
+    let max_amount_in = U256::exp10(20);
+    let checkpoint_pools = load_checkpoint(checkpoint_path)?;
+    let graph = TokenGraph::new(&checkpoint_pools.pools);
+    let context = offline.quote_context(&checkpoint_pools.tick_store);
+
+    let mut cycles = find_cycles(&graph, &[weth], 3);
+    cycles.retain(|cycle| cycle.spot_return() > 1.0);
+    cycles.sort_by(|a, b| b.spot_return().total_cmp(&a.spot_return()));
+
+    let mut sized = 0;
+    for cycle in &cycles {
+        let size = match optimal_trade_size(
+            &cycle.pools,
+            weth,
+            max_amount_in,
+            &context,
+            middleware.clone(),
+        )
+        .await?
+        {
+            Some(size) => size,
+            None => continue,
+        };
+        let hop_amounts = simulate_path_amounts(
+            &cycle.pools,
+            weth,
+            size.amount_in,
+            &context,
+            middleware.clone(),
+        )
+        .await?;
+        if hop_amounts
+            .iter()
+            .any(|amount| *amount < U256::from(MIN_CHECKED_HOP_AMOUNT))
+        {
+            continue;
+        }
+        sized += 1;
+
+        let profit_at = |amount_in: U256| {
+            let (context, middleware) = (&context, middleware.clone());
+            async move {
+                simulate_path_amounts(&cycle.pools, weth, amount_in, context, middleware)
+                    .await
+                    .map(|amounts| u256_to_f64(*amounts.last().unwrap()) - u256_to_f64(amount_in))
+                    .unwrap_or(f64::MIN)
+            }
+        };
+
+        let profit = u256_to_f64(size.profit);
+        // The pools round every hop to whole units, which shifts the simulated optimum of cycles
+        // through dust reserves slightly off the continuous one
+        let tolerance = profit * 1e-6 + 10.0;
+        for factor in [0.5, 0.9, 0.99, 0.999, 1.001, 1.01, 1.1, 2.0] {
+            let amount_in = f64_to_u256(u256_to_f64(size.amount_in) * factor);
+            if amount_in.is_zero() || amount_in > max_amount_in {
+                continue;
+            }
+
+            let nearby_profit = profit_at(amount_in).await;
+            eyre::ensure!(
+                nearby_profit <= profit + tolerance,
+                "Cycle {:?} makes {} at {} but {} at {}",
+                cycle.tokens,
+                profit,
+                size.amount_in,
+                nearby_profit,
+                amount_in
+            );
+        }
+
+        let golden_amount_in = f64_to_u256(
+            golden_section_search(
+                &cycle.pools,
+                weth,
+                max_amount_in,
+                &context,
+                middleware.clone(),
+            )
+            .await,
+        );
+        let golden_profit = profit_at(golden_amount_in).await;
+        eyre::ensure!(
+            (golden_profit - profit).abs() <= tolerance,
+            "Cycle {:?} makes {} at the closed form size {} but {} at the golden section size {}",
+            cycle.tokens,
+            profit,
+            size.amount_in,
+            golden_profit,
+            golden_amount_in
+        );
+
+        println!(
+            "{:?}: {} in, {} profit, golden section {} in",
+            cycle.tokens, size.amount_in, size.profit, golden_amount_in
+        );
+    }
+
+    eyre::ensure!(sized > 0, "No profitable WETH cycle in {}", checkpoint_path);
+    println!("Checked {} of {} profitable cycles", sized, cycles.len());
+
+    Ok(())
+}
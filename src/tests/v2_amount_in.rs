@@ -0,0 +1,68 @@
+use cfmms::pool::Pool;
+use ethers::types::U256;
+
+use crate::markets::{checkpoint::load_checkpoint, v2_amount_in};
+
+// Shares of the output reserve asked for, in millionths
+const CHECKED_SHARES: [u64; 5] = [1, 1_000, 100_000, 500_000, 999_000];
+
+// Checks on every V2 pool of a checkpoint that the pair's own math turns `v2_amount_in` into the
+// asked output, with at most the one unit getAmountIn rounds up by to spare, and that outputs the
+// reserves can't cover have no input
+pub async fn try_v2_amount_in(checkpoint_path: &str) -> eyre::Result<()> {
+    let mut checked = 0;
+
+    for pool in load_checkpoint(checkpoint_path)?.pools {
+        let Pool::UniswapV2(uniswap_v2_pool) = pool else {
+            continue;
+        };
+        // getAmountOut in cfmms is fixed at a 0.3% fee
+        if uniswap_v2_pool.fee != 300 {
+            continue;
+        }
+
+        for (token_in, reserve_out) in [
+            (uniswap_v2_pool.token_a, uniswap_v2_pool.reserve_1),
+            (uniswap_v2_pool.token_b, uniswap_v2_pool.reserve_0),
+        ] {
+            let reserve_out = U256::from(reserve_out);
+            eyre::ensure!(
+                v2_amount_in(&uniswap_v2_pool, token_in, reserve_out).is_none(),
+                "Pool {:?} quotes an input for its whole reserve",
+                uniswap_v2_pool.address
+            );
+
+            for share in CHECKED_SHARES {
+                let amount_out = reserve_out * share / 1_000_000;
+                if amount_out.is_zero() {
+                    continue;
+                }
+
+                let Some(amount_in) = v2_amount_in(&uniswap_v2_pool, token_in, amount_out) else {
+                    continue;
+                };
+                eyre::ensure!(
+                    uniswap_v2_pool.simulate_swap(token_in, amount_in) >= amount_out,
+                    "Pool {:?} gives less than {} for {}",
+                    uniswap_v2_pool.address,
+                    amount_out,
+                    amount_in
+                );
+                eyre::ensure!(
+                    amount_in < U256::from(2)
+                        || uniswap_v2_pool.simulate_swap(token_in, amount_in - 2) < amount_out,
+                    "Pool {:?} gives {} for less than {}",
+                    uniswap_v2_pool.address,
+                    amount_out,
+                    amount_in
+                );
+                checked += 1;
+            }
+        }
+    }
+
+    eyre::ensure!(checked > 0, "No V2 pool with reserves in {}", checkpoint_path);
+    println!("Checked {} exact output quotes", checked);
+
+    Ok(())
+}
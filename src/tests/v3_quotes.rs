@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use amms::amm::{
+    uniswap_v3::{Info, UniswapV3Pool as AmmsUniswapV3Pool},
+    AutomatedMarketMaker,
+};
+use cfmms::pool::UniswapV3Pool;
+use ethers::{
+    providers::{Http, Provider},
+    types::{H160, U256},
+};
+use serde::{Deserialize, Serialize};
+use uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick;
+
+use crate::{
+    abi::IUniswapV3Quoter,
+    config::Chain,
+    markets::uniswap_v3::{simulate_swap, sync_tick_data, TickData},
+};
+
+// Amounts quoted in each direction, as whole tokens of the token in over a power of ten
+const RECORDED_AMOUNTS: [(u64, u32); 4] = [(1, 2), (1, 0), (100, 0), (10_000, 0)];
+
+// A V3 pool with its state, tick data and quoter outputs, all read at the same block
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedPool {
+    pub address: H160,
+    pub token_a: H160,
+    pub token_a_decimals: u8,
+    pub token_b: H160,
+    pub token_b_decimals: u8,
+    pub fee: u32,
+    pub liquidity: u128,
+    pub sqrt_price: U256,
+    pub tick: i32,
+    pub tick_data: TickData,
+    pub quotes: Vec<RecordedQuote>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedQuote {
+    pub token_in: H160,
+    pub amount_in: U256,
+    // None when the quoter reverted
+    pub amount_out: Option<U256>,
+}
+
+impl RecordedPool {
+    fn pool(&self) -> UniswapV3Pool {
+        UniswapV3Pool::new(
+            self.address,
+            self.token_a,
+            self.token_a_decimals,
+            self.token_b,
+            self.token_b_decimals,
+            self.fee,
+            self.liquidity,
+            self.sqrt_price,
+            self.tick,
+            self.tick_data.tick_spacing,
+            0,
+        )
+    }
+}
+
+// Records the tick data of Ethereum V3 pools along with the quoter's outputs for a few amounts in
+// both directions at the block the ticks were read, for `try_v3_quotes` to check against offline
+pub async fn try_record_v3_quotes(
+    rpc_endpoint: String,
+    pools: &[&str],
+    fixture_path: &str,
+) -> eyre::Result<()> {
+    let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint)?);
+    let quoter = IUniswapV3Quoter::new(Chain::Ethereum.v3_quoter().unwrap(), middleware.clone());
+
+    let mut recorded_pools = vec![];
+    for pool in pools {
+        let mut pool =
+            UniswapV3Pool::new_from_address(H160::from_str(pool)?, middleware.clone()).await?;
+        let tick_data = sync_tick_data(&mut pool, middleware.clone()).await?;
+        let block_number = tick_data.block_number;
+
+        let mut quotes = vec![];
+        for (token_in, token_out, decimals) in [
+            (pool.token_a, pool.token_b, pool.token_a_decimals),
+            (pool.token_b, pool.token_a, pool.token_b_decimals),
+        ] {
+            for (whole, divisor) in RECORDED_AMOUNTS {
+                let amount_in = U256::from(whole) * U256::exp10(decimals as usize)
+                    / U256::exp10(divisor as usize);
+                // The quoter reverts when the pool can't take the whole input
+                let amount_out = quoter
+                    .quote_exact_input_single(
+                        token_in,
+                        token_out,
+                        pool.fee,
+                        amount_in,
+                        U256::zero(),
+                    )
+                    .block(block_number)
+                    .call()
+                    .await
+                    .ok();
+                quotes.push(RecordedQuote {
+                    token_in,
+                    amount_in,
+                    amount_out,
+                });
+            }
+        }
+
+        println!(
+            "Recorded {} quotes of {:?} at block {}",
+            quotes.len(),
+            pool.address,
+            block_number
+        );
+        recorded_pools.push(RecordedPool {
+            address: pool.address,
+            token_a: pool.token_a,
+            token_a_decimals: pool.token_a_decimals,
+            token_b: pool.token_b,
+            token_b_decimals: pool.token_b_decimals,
+            fee: pool.fee,
+            liquidity: pool.liquidity,
+            sqrt_price: pool.sqrt_price,
+            tick: pool.tick,
+            tick_data,
+            quotes,
+        });
+    }
+
+    write(fixture_path, serde_json::to_string_pretty(&recorded_pools)?)?;
+
+    Ok(())
+}
+
+// Checks that swaps replayed from recorded tick data give exactly the quoter's outputs
+pub async fn try_v3_quotes(fixture_path: &str) -> eyre::Result<()> {
+    let recorded_pools: Vec<RecordedPool> = serde_json::from_str(&read_to_string(fixture_path)?)?;
+
+    let mut checked = 0;
+    for recorded_pool in &recorded_pools {
+        let pool = recorded_pool.pool();
+        for quote in &recorded_pool.quotes {
+            let Some(quoted_amount_out) = quote.amount_out else {
+                continue;
+            };
+            let amount_out = simulate_swap(
+                &pool,
+                &recorded_pool.tick_data,
+                quote.token_in,
+                quote.amount_in,
+            )?;
+            eyre::ensure!(
+                amount_out == quoted_amount_out,
+                "Pool {:?} gives {} for {} of {:?}, the quoter {}",
+                pool.address,
+                amount_out,
+                quote.amount_in,
+                quote.token_in,
+                quoted_amount_out
+            );
+            checked += 1;
+        }
+    }
+
+    eyre::ensure!(checked > 0, "No quotes in {}", fixture_path);
+    println!("Checked {} V3 quotes", checked);
+
+    Ok(())
+}
+
+// Checks the local V3 swap against the amms port of the pool contract on a synthetic pool, with
+// positions below, around and above the price so swaps cross initialized ticks both ways. The
+// amms port only moves the tick at initialized ticks and never gets past an empty bitmap word, so
+// the positions and amounts keep every swap within one word.
+pub async fn try_v3_swap_against_amms() -> eyre::Result<()> {
+    let tick_spacing = 60;
+    let tick = 7_020;
+    // (tick lower, tick upper, liquidity), word 0 holds ticks 0 to 15300
+    let positions: [(i32, i32, u128); 5] = [
+        (60, 15_300, 10u128.pow(19)),
+        (6_000, 8_040, 5 * 10u128.pow(19)),
+        (6_600, 7_800, 10u128.pow(20)),
+        (7_200, 12_000, 2 * 10u128.pow(19)),
+        (1_200, 6_600, 3 * 10u128.pow(19)),
+    ];
+
+    let mut tick_data = TickData {
+        tick_spacing,
+        ..Default::default()
+    };
+    let mut ticks: HashMap<i32, Info> = HashMap::new();
+    let mut liquidity = 0;
+    for (tick_lower, tick_upper, position_liquidity) in positions {
+        if tick_lower <= tick && tick < tick_upper {
+            liquidity += position_liquidity;
+        }
+        for (tick, liquidity_net) in [
+            (tick_lower, position_liquidity as i128),
+            (tick_upper, -(position_liquidity as i128)),
+        ] {
+            *tick_data.ticks.entry(tick).or_default() += liquidity_net;
+            let info = ticks.entry(tick).or_default();
+            info.liquidity_gross += position_liquidity;
+            info.liquidity_net += liquidity_net;
+            info.initialized = true;
+
+            let compressed = tick / tick_spacing;
+            *tick_data
+                .tick_bitmap
+                .entry((compressed >> 8) as i16)
+                .or_default() |= U256::one() << (compressed & 255);
+        }
+    }
+
+    let token_a = H160::from_low_u64_be(1);
+    let token_b = H160::from_low_u64_be(2);
+    let sqrt_price = get_sqrt_ratio_at_tick(tick)?;
+    let pool = UniswapV3Pool::new(
+        H160::from_low_u64_be(3),
+        token_a,
+        18,
+        token_b,
+        18,
+        3000,
+        liquidity,
+        sqrt_price,
+        tick,
+        tick_spacing,
+        0,
+    );
+    let amms_pool = AmmsUniswapV3Pool {
+        address: pool.address,
+        token_a,
+        token_a_decimals: 18,
+        token_b,
+        token_b_decimals: 18,
+        liquidity,
+        sqrt_price,
+        fee: 3000,
+        tick,
+        tick_spacing,
+        tick_bitmap: tick_data.tick_bitmap.clone(),
+        ticks,
+    };
+
+    let mut checked = 0;
+    for token_in in [token_a, token_b] {
+        for exponent in 12..=19 {
+            let amount_in = U256::exp10(exponent);
+            let amount_out = simulate_swap(&pool, &tick_data, token_in, amount_in)?;
+            let amms_amount_out = amms_pool.simulate_swap(token_in, amount_in)?;
+            eyre::ensure!(
+                amount_out == amms_amount_out,
+                "{} of {:?} gives {}, amms {}",
+                amount_in,
+                token_in,
+                amount_out,
+                amms_amount_out
+            );
+            checked += 1;
+        }
+    }
+    println!("Checked {} V3 swaps against amms", checked);
+
+    Ok(())
+}
+
+// Checks that a swap replayed from tick data read around the price stops at the edge of the words
+// that were read instead of assuming they hold no ticks past it
+pub async fn try_v3_swap_word_range() -> eyre::Result<()> {
+    let tick_spacing = 60;
+    let tick = 7_020;
+    // One position across words -1 and 0
+    let (tick_lower, tick_upper, liquidity) = (-7_680, 15_300, 10u128.pow(20));
+
+    let mut tick_data = TickData {
+        tick_spacing,
+        ticks: HashMap::from([
+            (tick_lower, liquidity as i128),
+            (tick_upper, -(liquidity as i128)),
+        ]),
+        ..Default::default()
+    };
+    for tick in [tick_lower, tick_upper] {
+        let compressed = tick.div_euclid(tick_spacing);
+        *tick_data
+            .tick_bitmap
+            .entry((compressed >> 8) as i16)
+            .or_default() |= U256::one() << (compressed & 255);
+    }
+
+    let token_a = H160::from_low_u64_be(1);
+    let pool = UniswapV3Pool::new(
+        H160::from_low_u64_be(3),
+        token_a,
+        18,
+        H160::from_low_u64_be(2),
+        18,
+        3000,
+        liquidity,
+        get_sqrt_ratio_at_tick(tick)?,
+        tick,
+        tick_spacing,
+        0,
+    );
+
+    let amount_in = U256::exp10(24);
+    let amount_out = simulate_swap(&pool, &tick_data, token_a, amount_in)?;
+    tick_data.word_range = Some((0, 0));
+    let word_amount_out = simulate_swap(&pool, &tick_data, token_a, amount_in)?;
+    eyre::ensure!(
+        !word_amount_out.is_zero() && word_amount_out < amount_out,
+        "Word 0 alone gives {}, the whole bitmap {}",
+        word_amount_out,
+        amount_out
+    );
+    println!(
+        "Word 0 alone gives {} of the {} the whole bitmap gives",
+        word_amount_out, amount_out
+    );
+
+    Ok(())
+}
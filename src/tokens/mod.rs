@@ -0,0 +1,303 @@
+use std::{collections::HashMap, fs::read_to_string, str::FromStr};
+
+use ethers::types::{H160, U256};
+use serde::Deserialize;
+
+use crate::{
+    config::Chain,
+    error::ConfigError,
+    markets::{f64_to_u256, u256_to_f64},
+};
+
+pub mod transfer_tax;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenTag {
+    WrappedNative,
+    Stable,
+    Bridged,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub address: H160,
+    pub symbol: String,
+    pub decimals: u8,
+    pub tags: Vec<TokenTag>,
+    pub fee_on_transfer: bool,
+    pub rebasing: bool,
+}
+
+impl Token {
+    pub fn new(address: H160, symbol: &str, decimals: u8, tags: Vec<TokenTag>) -> Token {
+        Token {
+            address,
+            symbol: symbol.to_string(),
+            decimals,
+            tags,
+            fee_on_transfer: false,
+            rebasing: false,
+        }
+    }
+
+    pub fn has_tag(&self, tag: TokenTag) -> bool {
+        self.tags.contains(&tag)
+    }
+}
+
+// Tokens known for a chain, keyed by address
+#[derive(Debug, Clone, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<H160, Token>,
+    // Lowercase symbol to the token it resolves to, the first token inserted with a symbol keeps it
+    symbols: HashMap<String, H160>,
+    wrapped_native: Option<H160>,
+    // Measured share of each transfer the token keeps, for tokens that have been probed
+    transfer_taxes: HashMap<H160, f64>,
+}
+
+// Layout of a standard token list file, https://tokenlists.org
+#[derive(Debug, Deserialize)]
+struct TokenList {
+    tokens: Vec<TokenListEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenListEntry {
+    chain_id: usize,
+    address: String,
+    symbol: String,
+    decimals: u8,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> TokenRegistry {
+        TokenRegistry::default()
+    }
+
+    // Wrapped native token and the main stables of each chain
+    pub fn for_chain(chain: Chain) -> TokenRegistry {
+        use TokenTag::*;
+
+        let tokens: Vec<(&str, &str, u8, Vec<TokenTag>)> = match chain {
+            Chain::Ethereum => vec![
+                ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", "WETH", 18, vec![WrappedNative]),
+                ("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "USDC", 6, vec![Stable]),
+                ("0xdac17f958d2ee523a2206206994597c13d831ec7", "USDT", 6, vec![Stable]),
+                ("0x6B175474E89094C44Da98b954EedeAC495271d0F", "DAI", 18, vec![Stable]),
+                ("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599", "WBTC", 8, vec![Bridged]),
+            ],
+            Chain::Polygon => vec![
+                ("0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270", "WMATIC", 18, vec![WrappedNative]),
+                ("0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174", "USDC", 6, vec![Stable, Bridged]),
+                ("0xc2132D05D31c914a87C6611C10748AEb04B58e8F", "USDT", 6, vec![Stable, Bridged]),
+                ("0x8f3Cf7ad23Cd3CaDbD9735AFf958023239c6A063", "DAI", 18, vec![Stable, Bridged]),
+                ("0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619", "WETH", 18, vec![Bridged]),
+            ],
+            Chain::Optimism => vec![
+                ("0x4200000000000000000000000000000000000006", "WETH", 18, vec![WrappedNative]),
+                ("0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85", "USDC", 6, vec![Stable]),
+                ("0x94b008aA00579c1307B0EF2c499aD98a8ce58e58", "USDT", 6, vec![Stable, Bridged]),
+                ("0xDA10009cBd5D07dd0CeCc66161FC93D7c9000da1", "DAI", 18, vec![Stable, Bridged]),
+            ],
+            Chain::Arbitrum => vec![
+                ("0x82aF49447D8a07e3bd95BD0d56f35241523fBab1", "WETH", 18, vec![WrappedNative]),
+                ("0xaf88d065e77c8cC2239327C5EDb3A432268e5831", "USDC", 6, vec![Stable]),
+                ("0xFd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9", "USDT", 6, vec![Stable, Bridged]),
+                ("0xDA10009cBd5D07dd0CeCc66161FC93D7c9000da1", "DAI", 18, vec![Stable, Bridged]),
+            ],
+            Chain::Bsc => vec![
+                ("0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c", "WBNB", 18, vec![WrappedNative]),
+                ("0x55d398326f99059fF775485246999027B3197955", "USDT", 18, vec![Stable, Bridged]),
+                ("0x8AC76a51cc950d9822D68b83fE1Ad97B32Cd580d", "USDC", 18, vec![Stable, Bridged]),
+                ("0xe9e7CEA3DedcA5984780Bafc599bD69ADd087D56", "BUSD", 18, vec![Stable]),
+            ],
+            Chain::Base => vec![
+                ("0x4200000000000000000000000000000000000006", "WETH", 18, vec![WrappedNative]),
+                ("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", "USDC", 6, vec![Stable]),
+                ("0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb", "DAI", 18, vec![Stable, Bridged]),
+            ],
+        };
+
+        let mut registry = TokenRegistry::new();
+        for (address, symbol, decimals, tags) in tokens {
+            registry.insert(Token::new(
+                H160::from_str(address).unwrap(),
+                symbol,
+                decimals,
+                tags,
+            ));
+        }
+
+        registry
+    }
+
+    // Loads the entries for `chain_id` from a token list json file
+    pub fn from_token_list(path: &str, chain_id: usize) -> Result<TokenRegistry, ConfigError> {
+        let token_list: TokenList = serde_json::from_str(
+            &read_to_string(path).map_err(|err| ConfigError::ReadError(path.to_string(), err))?,
+        )
+        .map_err(|err| ConfigError::TokenListParseError(path.to_string(), err))?;
+
+        let mut registry = TokenRegistry::new();
+        for entry in token_list.tokens {
+            if entry.chain_id != chain_id {
+                continue;
+            }
+
+            let address = H160::from_str(&entry.address).map_err(|_| {
+                ConfigError::InvalidAddress(format!("token {:?}", entry.symbol), entry.address.clone())
+            })?;
+
+            let mut token = Token::new(address, &entry.symbol, entry.decimals, vec![]);
+            for tag in entry.tags {
+                match tag.to_lowercase().replace('_', "-").as_str() {
+                    "wrapped-native" => token.tags.push(TokenTag::WrappedNative),
+                    "stablecoin" | "stable" => token.tags.push(TokenTag::Stable),
+                    "bridged" => token.tags.push(TokenTag::Bridged),
+                    "fee-on-transfer" => token.fee_on_transfer = true,
+                    "rebasing" => token.rebasing = true,
+                    _ => {}
+                }
+            }
+
+            if let Some(wrapped_native) = registry.wrapped_native {
+                if token.has_tag(TokenTag::WrappedNative) && wrapped_native != address {
+                    return Err(ConfigError::DuplicateWrappedNative(
+                        path.to_string(),
+                        wrapped_native,
+                        address,
+                    ));
+                }
+            }
+            registry.insert(token);
+        }
+
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, token: Token) {
+        let address = token.address;
+        if let Some(previous) = self.tokens.remove(&address) {
+            self.unindex(&previous);
+        }
+
+        self.symbols
+            .entry(token.symbol.to_lowercase())
+            .or_insert(address);
+        if token.has_tag(TokenTag::WrappedNative) && self.wrapped_native.is_none() {
+            self.wrapped_native = Some(address);
+        }
+        self.tokens.insert(address, token);
+    }
+
+    fn unindex(&mut self, token: &Token) {
+        let symbol = token.symbol.to_lowercase();
+        if self.symbols.get(&symbol) == Some(&token.address) {
+            self.symbols.remove(&symbol);
+        }
+        if self.wrapped_native == Some(token.address) {
+            self.wrapped_native = None;
+        }
+    }
+
+    // Entries in `other` replace entries with the same address, and its symbols and wrapped
+    // native token take precedence
+    pub fn extend(&mut self, other: TokenRegistry) {
+        for token in other.tokens.into_values() {
+            self.insert(token);
+        }
+        self.symbols.extend(other.symbols);
+        if other.wrapped_native.is_some() {
+            self.wrapped_native = other.wrapped_native;
+        }
+        self.transfer_taxes.extend(other.transfer_taxes);
+    }
+
+    pub fn get(&self, address: H160) -> Option<&Token> {
+        self.tokens.get(&address)
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<&Token> {
+        self.symbols
+            .get(&symbol.to_lowercase())
+            .and_then(|address| self.get(*address))
+    }
+
+    pub fn with_tag(&self, tag: TokenTag) -> impl Iterator<Item = &Token> {
+        self.tokens.values().filter(move |token| token.has_tag(tag))
+    }
+
+    pub fn wrapped_native(&self) -> Option<&Token> {
+        self.wrapped_native.and_then(|address| self.get(address))
+    }
+
+    // The zero address stands for the native token and resolves to its wrapped token
+    pub fn resolve(&self, address: H160) -> H160 {
+        match address.is_zero() {
+            true => self
+                .wrapped_native()
+                .map(|token| token.address)
+                .unwrap_or(address),
+            false => address,
+        }
+    }
+
+    pub fn decimals(&self, address: H160) -> Option<u8> {
+        self.get(self.resolve(address)).map(|token| token.decimals)
+    }
+
+    pub fn is_fee_on_transfer(&self, address: H160) -> bool {
+        self.get(address).is_some_and(|token| token.fee_on_transfer)
+            || self.transfer_tax(address).is_some_and(|tax| tax > 0.0)
+    }
+
+    // None when the token hasn't been probed
+    pub fn transfer_tax(&self, address: H160) -> Option<f64> {
+        self.transfer_taxes.get(&address).copied()
+    }
+
+    pub fn set_transfer_tax(&mut self, address: H160, tax: f64) {
+        self.transfer_taxes.insert(address, tax.clamp(0.0, 1.0));
+    }
+
+    // What arrives when `amount` of the token is transferred
+    pub fn apply_transfer_tax(&self, address: H160, amount: U256) -> U256 {
+        match self.transfer_tax(address) {
+            Some(tax) if tax > 0.0 => amount.saturating_sub(f64_to_u256(u256_to_f64(amount) * tax)),
+            _ => amount,
+        }
+    }
+
+    // What has to be transferred for at least `amount` of the token to arrive, None when the tax
+    // takes everything
+    pub fn amount_before_transfer_tax(&self, address: H160, amount: U256) -> Option<U256> {
+        let tax = match self.transfer_tax(address) {
+            Some(tax) if tax >= 1.0 => return None,
+            Some(tax) if tax > 0.0 => tax,
+            _ => return Some(amount),
+        };
+
+        // The tax is applied in floating point, top up whatever the rounding left short
+        let mut gross_amount = f64_to_u256(u256_to_f64(amount) / (1.0 - tax)).max(amount);
+        for _ in 0..8 {
+            let arrived = self.apply_transfer_tax(address, gross_amount);
+            if arrived >= amount {
+                return Some(gross_amount);
+            }
+            gross_amount += amount - arrived;
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{encode, Token},
+    providers::{
+        call_raw::{spoof, RawCall},
+        Middleware,
+    },
+    types::{transaction::eip2718::TypedTransaction, Bytes, TransactionRequest, H160, U256},
+};
+
+use crate::{
+    abi::IErc20,
+    config::Config,
+    error::ExecutorError,
+    markets::{get_market, pool_depth, u256_to_f64},
+};
+
+// Runtime code of the stand-in put over a holder of the token with a state override. Called with
+// abi.encode(token, recipient, amount) it transfers `amount` of the holder's balance to
+// `recipient` and returns how much of it arrived, reverting when any of the calls fail.
+const TRANSFER_PROBE_CODE: &str = "0x6370a0823160e01b60005260203560045260206080602460006000355afa156100805763a9059cbb60e01b600052602035600452604035602452600060006044600060006000355af115610080576370a0823160e01b600052602035600452602060a0602460006000355afa156100805760805160a0510360005260206000f35b600080fd";
+
+// Runtime code put over the pool holding the token. Called with abi.encode(token, relay, amount)
+// it transfers `amount` to `relay`, which runs TRANSFER_PROBE_CODE to send everything that arrived
+// back to the pool, and returns what arrived at the relay and what arrived back at the pool.
+const ROUND_TRIP_PROBE_CODE: &str = "0x63a9059cbb60e01b600052602035600452604035602452600060006044600060006000355af11561007b576370a0823160e01b6000526020356004526020610100602460006000355afa1561007b57600035600052306020526101005160405260206101206060600060006020355af11561007b576040610100f35b600080fd";
+
+// Receives the probe transfer and sends it back, an address tokens have no reason to exempt
+const PROBE_RECIPIENT: H160 = H160([0x7a; 20]);
+
+// The probe moves this fraction of the holder's balance
+const PROBE_BALANCE_DIVISOR: u64 = 1000;
+
+// Shares of a transfer lost to the token's tax, on the way out of a pool and on the way into one.
+// Tokens often tax only one of the two, buys out of the pool or sells into it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbedTax {
+    pub buy: f64,
+    pub sell: f64,
+}
+
+impl ProbedTax {
+    // Routes apply one rate to every transfer, the larger one never leaves a quote short
+    pub fn rate(&self) -> f64 {
+        self.buy.max(self.sell)
+    }
+}
+
+// Measures the token's transfer tax in an eth_call, moving part of the pool's balance out to a
+// relay and back in. None when the pool has no balance, a transfer reverts or the endpoint
+// doesn't support state overrides.
+pub async fn probe_transfer_tax<M: 'static + Middleware>(
+    token: H160,
+    pool: H160,
+    middleware: Arc<M>,
+) -> Result<Option<ProbedTax>, ExecutorError<M>> {
+    let balance = IErc20::new(token, middleware.clone())
+        .balance_of(pool)
+        .call()
+        .await?;
+    let amount = balance / PROBE_BALANCE_DIVISOR;
+    if amount.is_zero() {
+        return Ok(None);
+    }
+
+    let mut state = spoof::state();
+    state
+        .account(pool)
+        .code(ROUND_TRIP_PROBE_CODE.parse::<Bytes>().unwrap());
+    state
+        .account(PROBE_RECIPIENT)
+        .code(TRANSFER_PROBE_CODE.parse::<Bytes>().unwrap());
+
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to(pool)
+        .data(encode(&[
+            Token::Address(token),
+            Token::Address(PROBE_RECIPIENT),
+            Token::Uint(amount),
+        ]))
+        .into();
+
+    let (bought, sold) = match middleware.provider().call_raw(&tx).state(&state).await {
+        Ok(received) if received.len() == 64 => (
+            U256::from_big_endian(&received[..32]),
+            U256::from_big_endian(&received[32..]),
+        ),
+        _ => return Ok(None),
+    };
+    if bought.is_zero() {
+        return Ok(Some(ProbedTax {
+            buy: 1.0,
+            sell: 1.0,
+        }));
+    }
+
+    Ok(Some(ProbedTax {
+        buy: 1.0 - u256_to_f64(bought.min(amount)) / u256_to_f64(amount),
+        sell: 1.0 - u256_to_f64(sold.min(bought)) / u256_to_f64(bought),
+    }))
+}
+
+// The pool holding the most `token` across its markets with the wrapped native token and the
+// intermediate tokens
+async fn deepest_holder<M: 'static + Middleware>(
+    token: H160,
+    configuration: &Config,
+    middleware: Arc<M>,
+) -> Result<Option<H160>, ExecutorError<M>> {
+    let mut base_tokens = vec![configuration.weth_address];
+    base_tokens.extend(configuration.intermediate_tokens.iter().copied());
+
+    let mut deepest: Option<(H160, f64)> = None;
+    for base_token in base_tokens {
+        if base_token == token {
+            continue;
+        }
+
+        let market =
+            match get_market(token, base_token, &configuration.dexes, middleware.clone()).await? {
+                Some(market) => market,
+                None => continue,
+            };
+        for pool in market.values() {
+            let depth = pool_depth(pool, token);
+            if deepest.is_none_or(|(_, deepest_depth)| depth > deepest_depth) {
+                deepest = Some((pool.address(), depth));
+            }
+        }
+    }
+
+    Ok(deepest.map(|(holder, _)| holder))
+}
+
+// Probes the tokens whose transfer tax isn't known yet, stores the measured rates in the
+// configuration's token registry and returns them. Does nothing unless `taxed_tokens` is set.
+pub async fn detect_transfer_taxes<M: 'static + Middleware>(
+    configuration: &mut Config,
+    tokens: &[H160],
+    middleware: Arc<M>,
+) -> Result<Vec<(H160, ProbedTax)>, ExecutorError<M>> {
+    let mut probed = vec![];
+    if !configuration.taxed_tokens {
+        return Ok(probed);
+    }
+
+    for token in tokens {
+        let token = configuration.tokens.resolve(*token);
+        if token == configuration.weth_address || configuration.tokens.transfer_tax(token).is_some() {
+            continue;
+        }
+
+        let holder = match deepest_holder(token, configuration, middleware.clone()).await? {
+            Some(holder) => holder,
+            None => continue,
+        };
+        if let Some(tax) = probe_transfer_tax(token, holder, middleware.clone()).await? {
+            configuration.tokens.set_transfer_tax(token, tax.rate());
+            probed.push((token, tax));
+        }
+    }
+
+    Ok(probed)
+}
@@ -1,188 +1,192 @@
-use std::{str::FromStr, sync::{Arc, Mutex}, collections::HashMap};
+use std::{str::FromStr, sync::Arc};
 
+use cfmms::pool::uniswap_v3::{MAX_SQRT_RATIO, MIN_SQRT_RATIO};
 use cfmms::pool::Pool;
 use ethabi::Token;
 use ethers::{
-    abi::AbiEncode, providers::Middleware, types::{H160, I256, U256}
+    providers::Middleware,
+    types::{H160, I256, U256},
 };
 // use eyre::Ok;
-use futures::{future::{BoxFuture, FutureExt}, executor::block_on};
-use lazy_static::lazy_static;
 use crate::{
     abi::IERC20_ABI,
     config::{self},
-    constants::{FIFTH_WEB_MULTICALL, UNISWAP_V2_FEE, WETH, USDC, USDT},
+    constants::{FIFTH_WEB_MULTICALL, PROTOCOL_FEE_DIVISOR},
     error::ExecutorError,
-    routing::{find_best_a_to_b_route, find_a_to_b_markets_and_route, find_a_to_x_to_b_markets_and_route, find_best_a_to_x_to_b_route, find_all_markets},
+    markets::{store::MarketStore, uniswap_v3::TickStore},
+    routing::{
+        exact_output::find_best_route_across_markets_exact_output,
+        find_best_path_route, find_path_markets,
+        gas::GasModel,
+        graph::{find_top_graph_routes, TokenGraph},
+        route::Route,
+        split::{disjoint_paths, find_best_split_route, SplitRoute, SPLIT_ROUTES},
+        sync_path_tick_data, QuoteContext, RouteOptions,
+    },
+    tokens::TokenRegistry,
 };
 
 pub(crate) mod types;
 
-use types::{SwapData, SwapMultiCall};
+use types::{SwapData, SwapMultiCall, SwapRequest};
 
-lazy_static! {
-    static ref GLOBAL_VEC: Arc<Mutex<Vec<Vec<H160>>>> = Arc::new(Mutex::new(Vec::new()));
+fn hex_calldata(calldata: &[u8]) -> String {
+    let mut hex_calldata = hex::encode(calldata);
+    hex_calldata.insert_str(0, "0x");
+    hex_calldata
 }
 
-// Async function example
-async fn async_add_to_global_vec(vec: Vec<H160>) {
-    let mut global_vec = GLOBAL_VEC.lock().unwrap();
-    global_vec.push(vec);
-}
+// Returns every path token_in -> connectors -> token_out of at most `max_hops` hops
+pub fn find_route(token_in: H160, token_out: H160, route_options: &RouteOptions) -> Vec<Vec<H160>> {
+    let mut routes = vec![];
+    let mut path = vec![token_in];
+
+    find_route_from(token_out, route_options, &mut path, &mut routes);
 
-fn compare_arc_option(
-    arc_opt: Arc<Option<H160>>,
-    opt: Option<H160>
-) -> bool {
-    let arc_value = &*arc_opt;
-    arc_value == &opt
+    routes
 }
 
-pub fn find_route(
-    token_in: Arc<Option<H160>>,
-    middle_tokens: Arc<HashMap<String, H160>>,
-    middle_tokens_names: Arc<Vec<&'static str>>,
-    deep: usize,
-    temp_res: Arc<Mutex<Vec<H160>>>
-) -> BoxFuture<'static, ()> {
-    async move {
-        let token_b = middle_tokens.get("TOKEN_B").cloned();
-        let usdc = middle_tokens.get("USDC").cloned();
-        let usdt = middle_tokens.get("USDT").cloned();
-        let token_in_cloned = token_in.clone();
-
-        let is_leaf = compare_arc_option(token_in_cloned, token_b);
-        if let Some(token) = *token_in {
-            temp_res.lock().unwrap().push(token);
-        }
-        if is_leaf && temp_res.lock().unwrap().to_vec().len() != 1 {
-            let future = async_add_to_global_vec(temp_res.lock().unwrap().to_vec());
-            block_on(future);
-            return ;
-        }
-        for temp in &*middle_tokens_names.clone() {
-            if deep == 3 {
-                break;
-            }
-            let next_token = middle_tokens.get(*temp).cloned();
-            let is_in_usdc = compare_arc_option(token_in.clone(), usdc);
-            let is_out_usdc = next_token == usdc;
-            let is_in_usdt = compare_arc_option(token_in.clone(), usdt);
-            let is_out_usdt = next_token == usdt;
-            if (is_in_usdc && is_out_usdt) || (is_in_usdt && is_out_usdc) || (*token_in == next_token) {
-                continue;
-            }
-            find_route(
-                Arc::new(next_token),
-                middle_tokens.clone(),
-                middle_tokens_names.clone(),
-                deep+1,
-                temp_res.clone()
-            ).await;
-            temp_res.lock().unwrap().pop();
-
-            if deep == 2 && next_token == token_b {
-                break;
-            }
+fn find_route_from(
+    token_out: H160,
+    route_options: &RouteOptions,
+    path: &mut Vec<H160>,
+    routes: &mut Vec<Vec<H160>>,
+) {
+    let token_in = *path.last().unwrap();
+    let deep = path.len() - 1;
+
+    if token_in == token_out && deep != 0 {
+        routes.push(path.clone());
+        return;
+    }
+    if deep == route_options.max_hops {
+        return;
+    }
+
+    // The last hop has to land on token_out
+    let next_tokens = if deep + 1 == route_options.max_hops {
+        vec![token_out]
+    } else {
+        [vec![token_out], route_options.connectors.clone()].concat()
+    };
+
+    for next_token in next_tokens {
+        if path.contains(&next_token) || route_options.is_skipped(token_in, next_token) {
+            continue;
         }
-    }.boxed()
+        path.push(next_token);
+        find_route_from(token_out, route_options, path, routes);
+        path.pop();
+    }
 }
 
-//Construct a final swap transaction calldata
+//Construct a final swap transaction calldata, routed through the path of `route_options` with
+// the most output after gas. V3 pools on the paths get their tick data synced into `tick_store`.
 pub async fn swap_transaction_calldata<M: 'static + Middleware>(
     configuration: &config::Config,
-    token_in: H160,
-    token_out: H160,
-    token_x: H160,
-    amount_in: U256,
-    slippage: u32,
-    receiver: H160,
+    request: &SwapRequest,
+    route_options: &RouteOptions,
+    tick_store: &mut TickStore,
+    market_store: &mut MarketStore,
     middleware: Arc<M>,
 ) -> Result<(SwapData, SwapMultiCall), ExecutorError<M>> {
+    let SwapRequest {
+        token_in,
+        token_out,
+        amount: amount_in,
+        slippage,
+        receiver,
+    } = *request;
     let mut amount_fixed_for_fee = amount_in;
     let mut protocol_fee = U256::zero();
     let bribe = U256::zero();
     let to = H160::from_str(FIFTH_WEB_MULTICALL).unwrap();
+    let resolved_token_in = configuration.tokens.resolve(token_in);
+    let resolved_token_out = configuration.tokens.resolve(token_out);
 
-    let middle_tokens_names = vec!["TOKEN_B", "WETH", "USDC", "USDT"];
-    let mut middle_tokens = HashMap::new();
+    if token_in.is_zero() {
+        protocol_fee = protocol_fee_in(amount_in);
+        amount_fixed_for_fee = amount_in - protocol_fee;
+    }
 
-    middle_tokens.insert(String::from("TOKEN_B"), token_out);
-    middle_tokens.insert(String::from("WETH"), H160::from_str(WETH).unwrap());
-    middle_tokens.insert(String::from("USDC"), H160::from_str(USDC).unwrap());
-    middle_tokens.insert(String::from("USDT"), H160::from_str(USDT).unwrap());
+    // Hops are ranked on gas priced in the tokens they pass through
+    let mut gas_tokens = vec![resolved_token_in, resolved_token_out];
+    gas_tokens.extend(&route_options.connectors);
+    let gas_model =
+        GasModel::from_config(configuration, &gas_tokens, market_store, middleware.clone()).await?;
+
+    let paths = find_route(resolved_token_in, resolved_token_out, route_options);
+    sync_path_tick_data(
+        &paths,
+        configuration,
+        market_store,
+        tick_store,
+        middleware.clone(),
+    )
+    .await?;
+    let context = QuoteContext {
+        tokens: &configuration.tokens,
+        tick_store,
+        gas_model: &gas_model,
+        v3_quoter: configuration.v3_quoter,
+    };
 
-    let middle_tokens = Arc::new(middle_tokens);
-    let middle_tokens_names = Arc::new(middle_tokens_names);
-    let token_route_in = Arc::new(Some(token_in));
-    let temp_res = Arc::new(Mutex::new(vec![]));
+    let mut best_route = find_best_path_route(
+        &paths,
+        amount_fixed_for_fee,
+        configuration,
+        market_store,
+        &context,
+        middleware.clone(),
+    )
+    .await?;
+
+    // The graph router also hops through tokens that aren't connectors, over the pools of the
+    // store that simulate locally, and its route is taken when it nets more after gas
+    let graph = TokenGraph::local(market_store.pools(), tick_store);
+    let graph_routes = find_top_graph_routes(
+        &graph,
+        resolved_token_in,
+        resolved_token_out,
+        amount_fixed_for_fee,
+        SPLIT_ROUTES,
+        route_options,
+        &context,
+        middleware.clone(),
+    )
+    .await?;
+    let net_amount_out =
+        |route: &Route| gas_model.net_amount_out(&route.tokens(), &route.amounts(), &route.pools());
+    if let Some(graph_route) = graph_routes.first() {
+        if net_amount_out(graph_route) > net_amount_out(&best_route) {
+            best_route = graph_route.clone();
+        }
+    }
 
-    find_route( token_route_in, Arc::clone(&middle_tokens), Arc::clone(&middle_tokens_names), 0, temp_res).await;
-    let routes = GLOBAL_VEC.lock().unwrap();
-    println!("==============================================this is the all routes from tree=====================================\n{:?}", routes);
-    
-    if token_in.is_zero() {
-        amount_fixed_for_fee -= amount_fixed_for_fee / 100;
-        protocol_fee = amount_in - amount_fixed_for_fee;
+    // A route moving the price too far is split across routes that don't share pools, when the
+    // split takes the whole amount in and nets more after gas
+    let mut split_route: Option<SplitRoute> = None;
+    if best_route.price_impact() > route_options.split_price_impact {
+        let mut routes = vec![best_route.clone()];
+        routes.extend(graph_routes);
+        let paths = disjoint_paths(&routes);
+        if paths.len() > 1 {
+            let split = find_best_split_route(
+                &paths,
+                resolved_token_in,
+                amount_fixed_for_fee,
+                route_options.split_chunks,
+                &context,
+                middleware.clone(),
+            )
+            .await?;
+            if split.amount_in == amount_fixed_for_fee
+                && split.net_amount_out(&gas_model) > net_amount_out(&best_route)
+            {
+                split_route = Some(split);
+            }
+        }
     }
-    
-    let mut tree_pool: Vec<Vec<Pool>> = Vec::new();
-    let mut tree_amount: Vec<Vec<U256>> = Vec::new();
-    tree_pool.resize(routes.len(), Vec::new());
-    tree_amount.resize(routes.len(), Vec::new());
-    let mut tree_best_route: Vec<Pool> = Vec::new();
-    let mut tree_best_amount_out = U256::zero();
-
-    // for i in 0..routes.len() {
-    //     for j in 0..routes[i].len() {
-
-    //         if j + 1 == routes[i].len() {
-    //             break;
-    //         }
-
-    //         let mut temp_amount_in: U256 = U256::zero();
-
-    //         if j == 0 {
-    //             temp_amount_in = amount_fixed_for_fee;
-    //         } else {
-    //             temp_amount_in = tree_amount[i][j-1];
-    //         }
-
-    //         let markets =
-    //             find_a_to_b_markets_and_route(routes[i][j], routes[i][j+1], configuration, middleware.clone()).await?;
-    //         let (pool, amount_out) =
-    //             find_best_a_to_b_route(markets, routes[i][j], temp_amount_in, middleware.clone()).await?;
-    //         // println!("this is the token pair ===============> {:?}, {:?}\n", routes[i][j], routes[i][j+1]);
-    //         // println!("this is the markets from finding markets ===============> {:?}\n", pool);
-
-    //         tree_pool[i].push(pool);
-    //         tree_amount[i].push(amount_out);
-    //     }
-    //     if tree_best_amount_out != U256::zero() && *tree_amount[i].last().unwrap() > tree_best_amount_out * 2 {
-    //         continue;
-    //     }
-    //     if tree_best_amount_out < *tree_amount[i].last().unwrap()  {
-    //         tree_best_amount_out = *tree_amount[i].last().unwrap();
-    //         tree_best_route = tree_pool[i].clone();
-    //     }
-    //     // println!("============== this is the each route and pool of tree ============== \n{:?}\n{:?}\n{:?}\n", tree_amount[i], routes[i], tree_pool[i]);
-    // }
-
-    // println!("==============================================this is the best route and amount from tree=====================================\n{:?}\n{:?}\n", tree_best_amount_out, tree_best_route);
-
-    // let all_markets = find_all_markets(token_in, token_out, configuration, middleware.clone()).await?;
-
-    // let multi_markets = 
-    //     find_a_to_x_to_b_markets_and_route(token_in, token_out, token_x, configuration, middleware.clone()).await?;
-    
-    // let (amounts_in, axb_amounts_out, axb_route) = 
-    //     find_best_a_to_x_to_b_route(token_in, token_out, token_x, amount_in, &multi_markets, middleware.clone()).await?;
-    
-    let markets =
-        find_a_to_b_markets_and_route(token_in, token_out, configuration, middleware.clone()).await?;
-
-    let (ab_pool, ab_amount_out) =
-        find_best_a_to_b_route(markets, token_in, amount_fixed_for_fee, middleware.clone()).await?;
 
     // Construct SwapCallData
     let mut swap_data: SwapData = SwapData {
@@ -191,6 +195,7 @@ pub async fn swap_transaction_calldata<M: 'static + Middleware>(
         amount_in: None,
         amount_out_min: None,
         protocol_fee: None,
+        amount_in_max: None,
         bribe,
         affiliate: U256::zero(),
         referrer: U256::zero(),
@@ -200,22 +205,14 @@ pub async fn swap_transaction_calldata<M: 'static + Middleware>(
         0 => 95 * 100,
         _ => slippage,
     };
-    let mut ab_route = vec![];
-    let mut best_route = vec![];
-    let mut best_amount_out = U256::zero();
-
-    best_amount_out = tree_best_amount_out;
-    best_route = tree_best_route;
-
-    ab_route.push(ab_pool);
-    // if axb_amounts_out.last().unwrap() > &ab_amount_out {
-    //     best_amount_out = *axb_amounts_out.last().unwrap();
-    //     best_route = axb_route;
-    // }
-    // else {
-        best_amount_out = ab_amount_out;
-        best_route = ab_route;
-    // }
+    // The output is taxed again on its way out of the pool
+    let route_amount_out = match &split_route {
+        Some(split) => split.amount_out,
+        None => best_route.amount_out,
+    };
+    let best_amount_out = configuration
+        .tokens
+        .apply_transfer_tax(resolved_token_out, route_amount_out);
 
     let amount_out_min = best_amount_out - best_amount_out * slippage_used / 10000;
 
@@ -234,90 +231,337 @@ pub async fn swap_transaction_calldata<M: 'static + Middleware>(
         swap_data.amount_out_min = Some(amount_out_min);
     };
 
+    // Native out is unwrapped by the multicall before it reaches the receiver
+    let recipient = match token_out.is_zero() {
+        true => to,
+        false => receiver,
+    };
+
     // Construct SwapMultiCall
-    let mut swap_multicall: SwapMultiCall = SwapMultiCall {
-        token_in_destination: to,
-        calls: vec![],
+    let swap_multicall = match &split_route {
+        Some(split) => split_multicall(split, recipient, slippage_used, &configuration.tokens)?,
+        None => {
+            let mut swap_multicall: SwapMultiCall = SwapMultiCall {
+                token_in_destination: to,
+                calls: vec![],
+            };
+            encode_route(
+                &best_route,
+                recipient,
+                RouteLimit::Slippage(slippage_used),
+                &configuration.tokens,
+                &mut swap_multicall,
+            )?;
+            swap_multicall
+        }
     };
 
-    // println!("========================= this is the best amount and route from A-B and A-X-B ======================= \n{:?}, {:?}\n", best_amount_out, best_route);
+    Ok((swap_data, swap_multicall))
+}
 
-    for best_pool in best_route {
-        match best_pool {
-            Pool::UniswapV2(uniswapv2_pool) => {
-                let mut swap_bytes: Vec<u8> = vec![];
+// Calldata for receiving exactly `request.amount` of the token out through the cheapest path of
+// `route_options`. The input is capped at the quoted amount in plus slippage instead of flooring
+// the output. V3 pools on the paths get their tick data synced into `tick_store`.
+pub async fn swap_transaction_calldata_exact_output<M: 'static + Middleware>(
+    configuration: &config::Config,
+    request: &SwapRequest,
+    route_options: &RouteOptions,
+    tick_store: &mut TickStore,
+    market_store: &mut MarketStore,
+    middleware: Arc<M>,
+) -> Result<(SwapData, SwapMultiCall), ExecutorError<M>> {
+    let to = H160::from_str(FIFTH_WEB_MULTICALL).unwrap();
+    let (token_in, token_out) = (request.token_in, request.token_out);
+    let resolved_token_in = configuration.tokens.resolve(token_in);
+    let resolved_token_out = configuration.tokens.resolve(token_out);
+
+    // Hops are ranked on gas priced in their own token in
+    let mut gas_tokens = vec![resolved_token_in];
+    gas_tokens.extend(&route_options.connectors);
+    let gas_model =
+        GasModel::from_config(configuration, &gas_tokens, market_store, middleware.clone()).await?;
+
+    let paths = find_route(resolved_token_in, resolved_token_out, route_options);
+    sync_path_tick_data(
+        &paths,
+        configuration,
+        market_store,
+        tick_store,
+        middleware.clone(),
+    )
+    .await?;
+    let context = QuoteContext {
+        tokens: &configuration.tokens,
+        tick_store,
+        gas_model: &gas_model,
+        v3_quoter: configuration.v3_quoter,
+    };
 
-                if token_in.is_zero() {
-                    swap_bytes.extend(&H160::from_str(WETH).unwrap().encode());
-                    swap_bytes.extend(&U256::from(UNISWAP_V2_FEE).encode());
-                } else if token_out.is_zero() {
-                } else {
-                    swap_bytes.push(0);
-                }
-                let swap_calldata = uniswapv2_pool.swap_calldata(amount_fixed_for_fee, U256::zero(), to, swap_bytes);
+    let mut best: Option<(Route, U256)> = None;
+    let mut diagnostics = vec![];
+    for path in paths {
+        let markets = match find_path_markets(
+            &path,
+            configuration,
+            market_store,
+            middleware.clone(),
+        )
+        .await?
+        {
+            Some(markets) => markets,
+            None => continue,
+        };
+
+        let route = match find_best_route_across_markets_exact_output(
+            request.amount,
+            resolved_token_in,
+            markets.iter().collect(),
+            &context,
+            middleware.clone(),
+        )
+        .await
+        {
+            Ok(route) => route,
+            Err(ExecutorError::NoViableRoute(_, _, path_diagnostics)) => {
+                diagnostics.extend(path_diagnostics);
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        // Every path spends the same token, so their gas compares in it
+        let total_cost = route.amount_in + gas_model.gas_cost(resolved_token_in, &route.pools());
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_cost)| total_cost < *best_cost)
+        {
+            best = Some((route, total_cost));
+        }
+    }
 
-                let mut hex_calldata = hex::encode(swap_calldata.clone());
-                hex_calldata.insert_str(0, "0x");
+    let route = match best {
+        Some((route, _)) => route,
+        None => {
+            return Err(ExecutorError::NoViableRoute(
+                resolved_token_in,
+                resolved_token_out,
+                diagnostics,
+            ))
+        }
+    };
 
-                // Push V2 swap call
-                swap_multicall
-                    .calls
-                    .push((uniswapv2_pool.address, hex_calldata));
+    let slippage_used = match request.slippage {
+        0 => 95 * 100,
+        _ => request.slippage,
+    };
+    let amount_in_max = route.amount_in + route.amount_in * slippage_used / 10000;
 
-                if token_in.is_zero() {
-                    swap_multicall.token_in_destination = to;
+    let mut swap_data = SwapData {
+        token_in: None,
+        token_out: None,
+        amount_in: None,
+        amount_out_min: Some(request.amount),
+        protocol_fee: None,
+        amount_in_max: Some(amount_in_max),
+        bribe: U256::zero(),
+        affiliate: U256::zero(),
+        referrer: U256::zero(),
+    };
 
-                    // Push Erc20 transfer call
-                    let transfer_input = vec![Token::Address(receiver), Token::Uint(best_amount_out)];
-                    let transfer_calldata = IERC20_ABI
-                        .function("transfer")?
-                        .encode_input(&transfer_input)
-                        .unwrap();
-                    let mut hex_calldata = hex::encode(transfer_calldata);
-                    hex_calldata.insert_str(0, "0x");
-                    swap_multicall.calls.push((token_out, hex_calldata));
-                } else if token_out.is_zero() {
-                    swap_multicall.token_in_destination = uniswapv2_pool.address;
-                }
-            }
-            Pool::UniswapV3(_uniswapv3_pool) => {
-                let mut swap_bytes: Vec<u8> = vec![];
-
-                if token_in.is_zero() {
-                    swap_bytes.extend(&H160::from_str(WETH).unwrap().encode());
-                    swap_bytes.extend(&U256::from(UNISWAP_V2_FEE).encode());
-                } else if token_out.is_zero() {
-                } else {
-                    swap_bytes.push(0);
-                }
+    if token_in.is_zero() {
+        swap_data.token_out = Some(token_out);
+        swap_data.protocol_fee = Some(protocol_fee_on_top(amount_in_max));
+    } else if token_out.is_zero() {
+        swap_data.token_in = Some(token_in);
+    } else {
+        swap_data.token_in = Some(token_in);
+        swap_data.token_out = Some(token_out);
+    }
 
-                let swap_calldata = _uniswapv3_pool.swap_calldata(to, true, I256::zero(), amount_fixed_for_fee, swap_bytes);
+    let mut swap_multicall = SwapMultiCall {
+        token_in_destination: to,
+        calls: vec![],
+    };
+    // Native out is unwrapped by the multicall before it reaches the receiver
+    let recipient = match token_out.is_zero() {
+        true => to,
+        false => request.receiver,
+    };
+    // A V3 first pool takes what it needs through the callback and the rest of the maximum is left
+    // with the multicall contract. A V2 first pair can't give change, it's sent the whole maximum
+    // and keeps what the swap doesn't need in its reserves.
+    encode_route(
+        &route,
+        recipient,
+        RouteLimit::AmountInMax(amount_in_max),
+        &configuration.tokens,
+        &mut swap_multicall,
+    )?;
 
-                let mut hex_calldata = hex::encode(swap_calldata.clone());
-                hex_calldata.insert_str(0, "0x");
+    Ok((swap_data, swap_multicall))
+}
 
-                // Push V2 swap call
-                swap_multicall
-                    .calls
-                    .push((_uniswapv3_pool.address, hex_calldata));
+// Protocol fee taken from a native amount in, the rest of it is swapped
+fn protocol_fee_in(amount_in: U256) -> U256 {
+    amount_in / PROTOCOL_FEE_DIVISOR
+}
 
-                if token_in.is_zero() {
-                    swap_multicall.token_in_destination = to;
+// Protocol fee to send on top of `amount_in_max` so that taking the fee from their sum still
+// leaves `amount_in_max` to swap
+fn protocol_fee_on_top(amount_in_max: U256) -> U256 {
+    let divisor = U256::from(PROTOCOL_FEE_DIVISOR);
+    // Smallest total whose fee leaves at least the maximum in, rounded up
+    let total = (amount_in_max * divisor + divisor - 2) / (divisor - 1);
+    protocol_fee_in(total)
+}
 
-                    // Push Erc20 transfer call
-                    let transfer_input = vec![Token::Address(receiver), Token::Uint(best_amount_out)];
+// What an encoded route holds its pools to
+#[derive(Debug, Clone, Copy)]
+enum RouteLimit {
+    // Exact input, the output may fall short of the quote by this many permyriad
+    Slippage(u32),
+    // Exact output, paying at most this much in
+    AmountInMax(U256),
+}
+
+// `amount` cut down to the share `keep` of it
+fn keep_share(amount: U256, keep: f64) -> U256 {
+    amount * U256::from((keep * 1e9) as u64) / U256::exp10(9)
+}
+
+// Appends the calls swapping through every hop of the route, with the output of the last hop
+// sent to `recipient`. Token in is expected in the multicall, V2 hops are paid by transferring to
+// the pair first and V3 hops through the swap callback. Output of a V2 hop goes straight to the
+// next pair when that pair is V2 as well.
+//
+// Exact input hops ask for their quoted output less an equal share of the slippage each, so the
+// last hop asks for the whole route's minimum. Outputs are concave in the input, a hop handed the
+// previous hop's minimum still makes its own unless the price moved against it. Exact output
+// routes ask V3 pools for the hop's amount out rather than offering its amount in, and pay a V2
+// first pair up to the maximum so that the price moving within slippage doesn't fail its K check.
+// Past the first hop the multicall only holds what arrived after the transfer tax of the token in.
+fn encode_route<M: Middleware>(
+    route: &Route,
+    recipient: H160,
+    limit: RouteLimit,
+    tokens: &TokenRegistry,
+    swap_multicall: &mut SwapMultiCall,
+) -> Result<(), ExecutorError<M>> {
+    let multicall = H160::from_str(FIFTH_WEB_MULTICALL).unwrap();
+
+    let hop_keep = match limit {
+        RouteLimit::Slippage(slippage) => {
+            (1.0 - slippage.min(10000) as f64 / 10000.0).powf(1.0 / route.hops.len().max(1) as f64)
+        }
+        RouteLimit::AmountInMax(_) => 1.0,
+    };
+    let mut keep = 1.0;
+    // What the previous hop is held to send on
+    let mut held = match limit {
+        RouteLimit::Slippage(_) => route.amount_in,
+        RouteLimit::AmountInMax(amount_in_max) => amount_in_max,
+    };
+
+    for (i, hop) in route.hops.iter().enumerate() {
+        let pool = &hop.pool;
+        let token_in = hop.token_in;
+        let amount_in = match i {
+            0 => held,
+            _ => tokens.apply_transfer_tax(token_in, held),
+        };
+        keep *= hop_keep;
+        let amount_out = keep_share(hop.amount_out, keep);
+        held = amount_out;
+
+        let hop_recipient = match route.hops.get(i + 1).map(|next_hop| next_hop.pool) {
+            None => recipient,
+            Some(Pool::UniswapV2(next_pool)) if matches!(pool, Pool::UniswapV2(_)) => {
+                next_pool.address
+            }
+            Some(_) => multicall,
+        };
+        // The previous V2 hop already paid this pair
+        let prepaid = i > 0 && matches!(route.hops[i - 1].pool, Pool::UniswapV2(_));
+
+        match pool {
+            Pool::UniswapV2(uniswap_v2_pool) => {
+                if !prepaid {
+                    let transfer_input = vec![
+                        Token::Address(uniswap_v2_pool.address),
+                        Token::Uint(amount_in),
+                    ];
                     let transfer_calldata = IERC20_ABI
                         .function("transfer")?
-                        .encode_input(&transfer_input)
-                        .unwrap();
-                    let mut hex_calldata = hex::encode(transfer_calldata);
-                    hex_calldata.insert_str(0, "0x");
-                    swap_multicall.calls.push((token_out, hex_calldata));
-                } else if token_out.is_zero() {
-                    swap_multicall.token_in_destination = _uniswapv3_pool.address;
+                        .encode_input(&transfer_input)?;
+                    swap_multicall
+                        .calls
+                        .push((token_in, hex_calldata(&transfer_calldata)));
                 }
+
+                let (amount_0_out, amount_1_out) = match token_in == uniswap_v2_pool.token_a {
+                    true => (U256::zero(), amount_out),
+                    false => (amount_out, U256::zero()),
+                };
+                let swap_calldata = uniswap_v2_pool.swap_calldata(
+                    amount_0_out,
+                    amount_1_out,
+                    hop_recipient,
+                    vec![],
+                );
+                swap_multicall
+                    .calls
+                    .push((uniswap_v2_pool.address, hex_calldata(&swap_calldata)));
+            }
+            Pool::UniswapV3(uniswap_v3_pool) => {
+                let zero_for_one = token_in == uniswap_v3_pool.token_a;
+                let sqrt_price_limit = match zero_for_one {
+                    true => MIN_SQRT_RATIO + 1,
+                    false => MAX_SQRT_RATIO - 1,
+                };
+                // A negative amount specified asks the pool for an exact output
+                let amount_specified = match limit {
+                    RouteLimit::AmountInMax(_) => -I256::from_raw(amount_out),
+                    RouteLimit::Slippage(_) => I256::from_raw(amount_in),
+                };
+                let swap_calldata = uniswap_v3_pool.swap_calldata(
+                    hop_recipient,
+                    zero_for_one,
+                    amount_specified,
+                    sqrt_price_limit,
+                    vec![0],
+                );
+                swap_multicall
+                    .calls
+                    .push((uniswap_v3_pool.address, hex_calldata(&swap_calldata)));
             }
         }
     }
-    Ok((swap_data, swap_multicall))
+
+    Ok(())
+}
+
+// Encodes every path of a split route into the multicall, each held to `slippage` permyriad of its
+// quoted output, see `encode_route`
+pub fn split_multicall<M: Middleware>(
+    split: &SplitRoute,
+    receiver: H160,
+    slippage: u32,
+    tokens: &TokenRegistry,
+) -> Result<SwapMultiCall, ExecutorError<M>> {
+    let mut swap_multicall = SwapMultiCall {
+        token_in_destination: H160::from_str(FIFTH_WEB_MULTICALL).unwrap(),
+        calls: vec![],
+    };
+
+    for allocation in &split.allocations {
+        encode_route(
+            allocation,
+            receiver,
+            RouteLimit::Slippage(slippage),
+            tokens,
+            &mut swap_multicall,
+        )?;
+    }
+
+    Ok(swap_multicall)
 }
@@ -1,5 +1,17 @@
 use ethers::types::{H160, U256};
 
+// A swap to build a transaction for. `amount` is the amount in of exact input swaps and the
+// amount out of exact output ones, the zero address stands for the native token.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapRequest {
+    pub token_in: H160,
+    pub token_out: H160,
+    pub amount: U256,
+    // Permyriad, 0 for the default
+    pub slippage: u32,
+    pub receiver: H160,
+}
+
 #[derive(Debug)]
 pub struct SwapData {
     pub token_in: Option<H160>,
@@ -7,6 +19,8 @@ pub struct SwapData {
     pub amount_in: Option<U256>,
     pub amount_out_min: Option<U256>,
     pub protocol_fee: Option<U256>,
+    // Set for exact output swaps, `amount_out_min` is then the exact amount out
+    pub amount_in_max: Option<U256>,
     pub bribe: U256,
     pub affiliate: U256,
     pub referrer: U256,